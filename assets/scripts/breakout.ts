@@ -1,33 +1,18 @@
+import { Scoreboard, Velocity, Ball, KeyCode, Input } from "./breakout_types";
+
 let firstIteration = true;
 let i = 0;
 
-type Scoreboard = {
-  score: number;
-};
-const Scoreboard: BevyType<Scoreboard> = { typeName: "breakout::Scoreboard" };
-
-type Velocity = {
-  0: Vec3;
-};
-const Velocity: BevyType<Velocity> = { typeName: "breakout::Velocity" };
-
-type Ball = unknown;
-const Ball: BevyType<Ball> = { typeName: "breakout::Ball" };
-
-type KeyCode = unknown; // enum handling is not implemented
-const KeyCode: BevyType<KeyCode> = { typeName: "bevy_input::keyboard::KeyCode" };
-
-type Input<T> = {
-  pressed: (key: T) => boolean,
-  just_pressed: (key: T) => boolean,
-  press: (key: T) => void,
-  get_pressed: () => T[],
-};
-const Input: <T>(T: BevyType<T>) => BevyType<Input<T>> = (T) => ({
-  typeName: `bevy_input::input::Input<${T.typeName}>`,
-});
-
-function run() {
+// Keep the frame counter when the script is hot reloaded
+export function saveState() {
+  return { i, firstIteration };
+}
+
+export function restoreState(state: { i: number; firstIteration: boolean }) {
+  ({ i, firstIteration } = state);
+}
+
+export function update() {
   i++;
   if (i % 60 == 0) {
     let score = world.resource(Scoreboard)!;
@@ -56,7 +41,3 @@ function run() {
     }*/
   }
 }
-
-export default {
-  update: run,
-};
@@ -0,0 +1,25 @@
+export type Scoreboard = {
+  score: number;
+};
+export const Scoreboard: BevyType<Scoreboard> = { typeName: "breakout::Scoreboard" };
+
+export type Velocity = {
+  0: Vec3;
+};
+export const Velocity: BevyType<Velocity> = { typeName: "breakout::Velocity" };
+
+export type Ball = unknown;
+export const Ball: BevyType<Ball> = { typeName: "breakout::Ball" };
+
+export type KeyCode = unknown; // enum handling is not implemented
+export const KeyCode: BevyType<KeyCode> = { typeName: "bevy_input::keyboard::KeyCode" };
+
+export type Input<T> = {
+  pressed: (key: T) => boolean,
+  just_pressed: (key: T) => boolean,
+  press: (key: T) => void,
+  get_pressed: () => T[],
+};
+export const Input: <T>(T: BevyType<T>) => BevyType<Input<T>> = (T) => ({
+  typeName: `bevy_input::input::Input<${T.typeName}>`,
+});
@@ -1,5 +1,5 @@
 let firstIteration = true;
-function run() {
+export function update() {
   if (firstIteration) {
     firstIteration = false;
     // info("Components: " + world.components.map(info => info.name).join(", "));
@@ -16,7 +16,3 @@ function run() {
     }
   }
 }
-
-export default {
-  update: run,
-};
@@ -1,6 +1,12 @@
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
-use bevy::asset::{AssetLoader, BoxedFuture, LoadedAsset};
+use bevy::{
+    asset::{AssetLoader, AssetPath, BoxedFuture, Handle, LoadedAsset},
+    prelude::Resource,
+};
 use bevy_reflect::TypeUuid;
 
 #[derive(TypeUuid)]
@@ -8,9 +14,29 @@ use bevy_reflect::TypeUuid;
 pub struct JsScript {
     pub source: String,
     pub path: PathBuf,
+    /// The asset paths of the scripts imported by this script.
+    pub dependencies: Vec<PathBuf>,
+    /// Maps locations in the transpiled [`source`][Self::source] back to the original script.
+    pub source_map: sourcemap::SourceMap,
 }
 
-pub struct JsScriptLoader;
+impl JsScript {
+    /// Returns weak handles to the scripts imported by this script.
+    pub fn dependency_handles(&self) -> impl Iterator<Item = Handle<JsScript>> + '_ {
+        self.dependencies
+            .iter()
+            .map(|path| Handle::weak(AssetPath::from(path.as_path()).into()))
+    }
+}
+
+/// Transpile errors reported by the [`JsScriptLoader`], waiting to be sent as
+/// [`JsScriptError`][crate::JsScriptError] events.
+#[derive(Resource, Clone, Default)]
+pub(crate) struct TranspileErrors(pub Arc<Mutex<Vec<(PathBuf, String)>>>);
+
+pub struct JsScriptLoader {
+    pub(crate) transpile_errors: TranspileErrors,
+}
 impl AssetLoader for JsScriptLoader {
     fn load<'a>(
         &'a self,
@@ -20,12 +46,28 @@ impl AssetLoader for JsScriptLoader {
         Box::pin(async move {
             let source = String::from_utf8(bytes.to_vec())?;
 
-            let source = crate::transpile::transpile(load_context.path(), &source)?;
+            let transpiled =
+                crate::transpile::transpile(load_context.path(), &source).map_err(|e| {
+                    self.transpile_errors
+                        .0
+                        .lock()
+                        .unwrap()
+                        .push((load_context.path().to_path_buf(), format!("{e:?}")));
+                    e
+                })?;
 
-            load_context.set_default_asset(LoadedAsset::new(JsScript {
-                source,
+            // Make the asset server load the imported scripts
+            let mut asset = LoadedAsset::new(JsScript {
+                source: transpiled.code,
                 path: load_context.path().to_path_buf(),
-            }));
+                dependencies: transpiled.dependencies.clone(),
+                source_map: transpiled.source_map,
+            });
+            for dependency in &transpiled.dependencies {
+                asset.add_dependency(dependency.as_path().into());
+            }
+
+            load_context.set_default_asset(asset);
             Ok(())
         })
     }
@@ -0,0 +1,269 @@
+use std::{any::Any, collections::BTreeMap};
+
+use bevy::{
+    ecs::event::{Event, ManualEventReader},
+    prelude::*,
+    utils::HashMap,
+};
+
+use crate::{
+    asset::JsScript,
+    handle_script_error,
+    runtime::{JsRuntime, JsRuntimeApi},
+    ActiveScripts, JsScriptComponent, ScriptErrorPolicy, ScriptErrorTracker,
+};
+
+/// The handlers registered by scripts with `events.on()`, keyed by the id of the subscription
+///
+/// The handlers themselves are kept alive by the runtime, which only knows them by their id.
+#[derive(Resource, Default)]
+pub(crate) struct EventSubscriptions {
+    next_id: u32,
+    subscriptions: BTreeMap<u32, EventSubscription>,
+}
+
+pub(crate) struct EventSubscription {
+    pub(crate) script: Handle<JsScript>,
+    /// The entity of the script instance that registered the handler, if any
+    pub(crate) entity: Option<Entity>,
+    pub(crate) type_name: String,
+}
+
+impl EventSubscriptions {
+    /// The id that the next subscription will get. Ids are increasing, so this can be used to tell
+    /// apart the subscriptions made before and after some point.
+    pub(crate) fn next_id(&self) -> u32 {
+        self.next_id
+    }
+
+    pub(crate) fn subscribe(
+        &mut self,
+        script: &Handle<JsScript>,
+        entity: Option<Entity>,
+        type_name: String,
+    ) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscriptions.insert(
+            id,
+            EventSubscription {
+                script: script.clone_weak(),
+                entity,
+                type_name,
+            },
+        );
+        id
+    }
+
+    pub(crate) fn get(&self, id: u32) -> Option<&EventSubscription> {
+        self.subscriptions.get(&id)
+    }
+
+    /// Removes the subscriptions of a script that match the filter, returning their ids
+    pub(crate) fn remove(
+        &mut self,
+        script: &Handle<JsScript>,
+        filter: impl Fn(u32, &EventSubscription) -> bool,
+    ) -> Vec<u32> {
+        let mut removed = Vec::new();
+        self.subscriptions.retain(|&id, subscription| {
+            let remove = &subscription.script == script && filter(id, subscription);
+            if remove {
+                removed.push(id);
+            }
+            !remove
+        });
+        removed
+    }
+}
+
+/// Removes the subscriptions of a script that match the filter, and drops their handlers
+///
+/// This has to be done while the script is loaded, since the handlers are dropped on its behalf.
+pub(crate) fn remove_event_handlers(
+    runtime: &JsRuntime,
+    script: &Handle<JsScript>,
+    filter: impl Fn(u32, &EventSubscription) -> bool,
+    world: &mut World,
+) {
+    let removed = world
+        .resource_mut::<EventSubscriptions>()
+        .remove(script, filter);
+    if removed.is_empty() || !runtime.has_loaded(script) {
+        return;
+    }
+
+    let result = runtime.run_global_fn(
+        script,
+        None,
+        "bevyModJsScriptingRemoveEventHandlers",
+        &[serde_json::json!(removed)],
+        world,
+    );
+    if let Err(error) = result {
+        error!("{error}");
+        world.send_event(error);
+    }
+}
+
+/// The functions used to read and send the events of the types registered with
+/// [`add_js_event`][crate::AddJsSystem::add_js_event], keyed by type name
+#[derive(Resource, Default, Deref, DerefMut)]
+pub(crate) struct ScriptEventTypes(HashMap<String, ScriptEventFns>);
+
+impl ScriptEventTypes {
+    pub(crate) fn get_fns(&self, type_name: &str) -> anyhow::Result<ScriptEventFns> {
+        self.get(type_name)
+            .copied()
+            .ok_or_else(|| anyhow::format_err!("`{type_name}` is not a script event type"))
+    }
+}
+
+/// Type-erased functions reading and sending the events of a type
+#[derive(Clone, Copy)]
+pub(crate) struct ScriptEventFns {
+    /// Reads the events sent since the last read with the given cursor, creating the cursor on
+    /// the first read
+    pub(crate) read: fn(&World, &mut Option<Box<dyn Any>>) -> Vec<Box<dyn Reflect>>,
+    /// Sends an event built from the given value
+    pub(crate) send: fn(&mut World, &dyn Reflect) -> anyhow::Result<()>,
+}
+
+impl ScriptEventFns {
+    pub(crate) fn new<E: Event + FromReflect>() -> Self {
+        Self {
+            read: read_events::<E>,
+            send: send_event::<E>,
+        }
+    }
+}
+
+fn read_events<E: Event + Reflect>(
+    world: &World,
+    reader: &mut Option<Box<dyn Any>>,
+) -> Vec<Box<dyn Reflect>> {
+    let reader = reader
+        .get_or_insert_with(|| Box::new(ManualEventReader::<E>::default()))
+        .downcast_mut::<ManualEventReader<E>>()
+        .unwrap();
+    reader
+        .iter(world.resource::<Events<E>>())
+        .map(|event| event.clone_value())
+        .collect()
+}
+
+fn send_event<E: Event + FromReflect>(
+    world: &mut World,
+    value: &dyn Reflect,
+) -> anyhow::Result<()> {
+    let event = E::from_reflect(value)
+        .ok_or_else(|| anyhow::format_err!("Value is not a `{}`", std::any::type_name::<E>()))?;
+    world.send_event(event);
+    Ok(())
+}
+
+/// The events sent during the current frame, keyed by the type name of the event types registered
+/// with [`add_js_event`][crate::AddJsSystem::add_js_event]
+#[derive(Resource, Default, Deref, DerefMut)]
+pub(crate) struct ScriptEvents(HashMap<String, Vec<Box<dyn Reflect>>>);
+
+/// Collects the events of a type so that they can be passed to the handlers of the scripts
+pub(crate) fn collect_script_events<E: Event + Reflect>(
+    mut reader: EventReader<E>,
+    mut script_events: ResMut<ScriptEvents>,
+) {
+    let events = script_events
+        .entry(std::any::type_name::<E>().to_owned())
+        .or_default();
+    events.extend(reader.iter().map(|event| event.clone_value()));
+}
+
+/// Runs the event handlers of the scripts and their instances for the events collected during the
+/// frame
+///
+/// Each handler is run separately, so an error thrown by one handler doesn't keep the others from
+/// running, and errors are handled according to the [`ScriptErrorPolicy`]. If the script of a
+/// handler is one of the [`ActiveScripts`], the handler only runs while the script is enabled.
+pub(crate) fn dispatch_script_events(world: &mut World) {
+    let runtime = world.remove_non_send_resource::<JsRuntime>().unwrap();
+    let mut error_tracker = world.remove_resource::<ScriptErrorTracker>().unwrap();
+    let error_policy = *world.resource::<ScriptErrorPolicy>();
+
+    // The subscriptions with events to pass to their handler
+    let script_events = world.resource::<ScriptEvents>();
+    let subscriptions = world
+        .resource::<EventSubscriptions>()
+        .subscriptions
+        .iter()
+        .filter(|(_, subscription)| {
+            script_events
+                .get(&subscription.type_name)
+                .map_or(false, |events| !events.is_empty())
+        })
+        .map(|(&id, subscription)| {
+            (
+                id,
+                subscription.script.clone_weak(),
+                subscription.entity,
+                subscription.type_name.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for (id, script, entity, type_name) in &subscriptions {
+        let enabled = world
+            .resource::<ActiveScripts>()
+            .get(script)
+            .map_or(true, |settings| settings.enabled);
+        // The subscription may have been removed by a handler that ran before
+        let is_subscribed = world.resource::<EventSubscriptions>().get(*id).is_some();
+        if !enabled
+            || !is_subscribed
+            || error_tracker.is_disabled(script, *entity)
+            || !runtime.has_loaded(script)
+        {
+            continue;
+        }
+
+        let result = runtime.run_global_fn(
+            script,
+            *entity,
+            "bevyModJsScriptingDispatchEvents",
+            &[serde_json::json!(id)],
+            world,
+        );
+        let fn_name = format!("events.on({type_name})");
+        let errors = error_tracker.record(script, *entity, &fn_name, result.is_err());
+
+        if let Err(error) = result {
+            error!("{error}");
+            if handle_script_error(
+                error_policy,
+                &mut error_tracker,
+                script,
+                *entity,
+                errors,
+                &error,
+            ) {
+                match entity {
+                    Some(entity) => {
+                        if let Some(mut entity) = world.get_entity_mut(*entity) {
+                            entity.remove::<JsScriptComponent>();
+                        }
+                    }
+                    None => {
+                        world.resource_mut::<ActiveScripts>().shift_remove(script);
+                    }
+                }
+            }
+            world.send_event(error);
+        }
+    }
+
+    for events in world.resource_mut::<ScriptEvents>().values_mut() {
+        events.clear();
+    }
+
+    world.insert_resource(error_tracker);
+    world.insert_non_send_resource(runtime);
+}
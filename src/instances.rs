@@ -0,0 +1,172 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    asset::JsScript,
+    events, handle_script_error,
+    runtime::{JsRuntime, JsRuntimeApi},
+    ActiveScripts, ScriptErrorPolicy, ScriptErrorTracker, SeparateScriptFns,
+};
+
+/// Component attaching a script to an entity
+///
+/// A separate instance of the script is created for every entity that the script is attached to.
+/// The stage functions of the script, along with its `init` and `onUnload` hooks, are called with
+/// `this` set to the instance, and `this.entity` holds the entity. The instance is destroyed when
+/// the component is removed or the entity is despawned, or when the script is unloaded.
+///
+/// The instances share the module of the script, so the variables declared at the top level of the
+/// script are shared by all of them, and state specific to an entity has to be stored on `this`.
+#[derive(Component, Reflect, Default, Clone, Debug, Deref, DerefMut)]
+#[reflect(Component)]
+pub struct JsScriptComponent(pub Handle<JsScript>);
+
+/// The scripts that have an instance for each entity
+#[derive(Resource, Default, Deref, DerefMut)]
+pub(crate) struct ScriptInstances(HashMap<Entity, Handle<JsScript>>);
+
+/// Creates the instances of the scripts attached to entities once the scripts have been loaded,
+/// and removes the instances of the scripts that have been detached from their entities.
+pub(crate) fn update_script_instances(runtime: &JsRuntime, world: &mut World) {
+    let mut instances = world.remove_resource::<ScriptInstances>().unwrap();
+
+    // Instances are removed along with their script when it is unloaded
+    instances.retain(|_, handle| runtime.has_loaded(handle));
+
+    let removed_instances = instances
+        .iter()
+        .filter(|&(&entity, handle)| {
+            world
+                .get::<JsScriptComponent>(entity)
+                .map_or(true, |script| &script.0 != handle)
+        })
+        .map(|(&entity, handle)| (entity, handle.clone_weak()))
+        .collect::<Vec<_>>();
+    for (entity, handle) in &removed_instances {
+        instances.remove(entity);
+        run_instance_hook(runtime, handle, *entity, "onUnload", world);
+        events::remove_event_handlers(
+            runtime,
+            handle,
+            |_, subscription| subscription.entity == Some(*entity),
+            world,
+        );
+        runtime.remove_instance(handle, *entity);
+        world
+            .resource_mut::<ScriptErrorTracker>()
+            .remove_instance(handle, *entity);
+    }
+
+    let new_instances = world
+        .query::<(Entity, &JsScriptComponent)>()
+        .iter(world)
+        .filter(|(entity, script)| runtime.has_loaded(script) && !instances.contains_key(entity))
+        .map(|(entity, script)| (entity, script.clone_weak()))
+        .collect::<Vec<_>>();
+    for (entity, handle) in &new_instances {
+        runtime.create_instance(handle, *entity);
+        instances.insert(*entity, handle.clone_weak());
+        run_instance_hook(runtime, handle, *entity, "init", world);
+    }
+
+    world.insert_resource(instances);
+}
+
+/// Runs the `onUnload` hook of the instances of a script that is being unloaded, and forgets them.
+pub(crate) fn unload_script_instances(
+    runtime: &JsRuntime,
+    handle: &Handle<JsScript>,
+    world: &mut World,
+) {
+    let mut entities = world
+        .resource::<ScriptInstances>()
+        .iter()
+        .filter(|(_, instance_handle)| *instance_handle == handle)
+        .map(|(&entity, _)| entity)
+        .collect::<Vec<_>>();
+    entities.sort_unstable();
+
+    for entity in entities {
+        run_instance_hook(runtime, handle, entity, "onUnload", world);
+        world.resource_mut::<ScriptInstances>().remove(&entity);
+    }
+}
+
+/// Runs the function `fn_name` of every script instance, in the order of their entities, or of
+/// the instances of `system_script` only if it is set.
+///
+/// If the script of an instance is one of the [`ActiveScripts`], the instance only runs when the
+/// script would. Errors are handled like the errors of the scripts, except that
+/// [`ScriptErrorPolicy::Unload`] removes the [`JsScriptComponent`] of the entity.
+pub(crate) fn run_instances_fn(
+    runtime: &JsRuntime,
+    fn_name: &str,
+    system_script: Option<&Handle<JsScript>>,
+    error_policy: ScriptErrorPolicy,
+    error_tracker: &mut ScriptErrorTracker,
+    world: &mut World,
+) {
+    let separate_fns = world.resource::<SeparateScriptFns>();
+    let mut instances = world
+        .resource::<ScriptInstances>()
+        .iter()
+        .filter(|(_, handle)| separate_fns.runs_script_fn(system_script, handle, fn_name))
+        .map(|(&entity, handle)| (entity, handle.clone_weak()))
+        .collect::<Vec<_>>();
+    instances.sort_unstable_by_key(|(entity, _)| *entity);
+
+    for (entity, handle) in &instances {
+        // Skip instances whose entity has been despawned or whose script has been detached since
+        // the instances were last updated.
+        if world
+            .get::<JsScriptComponent>(*entity)
+            .map_or(true, |script| &script.0 != handle)
+        {
+            continue;
+        }
+
+        let (enabled, run_criteria) = match world.resource::<ActiveScripts>().get(handle) {
+            Some(settings) => (settings.enabled, settings.run_criteria.clone()),
+            None => (true, None),
+        };
+        if !enabled
+            || error_tracker.is_disabled(handle, Some(*entity))
+            || !run_criteria.map_or(true, |run_criteria| run_criteria(world))
+        {
+            continue;
+        }
+
+        let result = runtime.run_instance(handle, *entity, fn_name, &[], world);
+        let errors = error_tracker.record(handle, Some(*entity), fn_name, result.is_err());
+
+        if let Err(error) = result {
+            error!(?entity, "{error}");
+            if handle_script_error(
+                error_policy,
+                error_tracker,
+                handle,
+                Some(*entity),
+                errors,
+                &error,
+            ) {
+                if let Some(mut entity) = world.get_entity_mut(*entity) {
+                    entity.remove::<JsScriptComponent>();
+                }
+            }
+            world.send_event(error);
+        }
+    }
+}
+
+/// Runs a lifecycle hook of a script instance, reporting the error if it throws one.
+fn run_instance_hook(
+    runtime: &JsRuntime,
+    handle: &Handle<JsScript>,
+    entity: Entity,
+    hook: &str,
+    world: &mut World,
+) {
+    if let Err(error) = runtime.run_instance(handle, entity, hook, &[], world) {
+        error!(?entity, "{error}");
+        world.send_event(error);
+    }
+}
@@ -2,46 +2,312 @@
 #![forbid(unsafe_code)]
 
 mod asset;
+mod events;
+mod instances;
 mod runtime;
 mod transpile;
 
-use asset::JsScriptLoader;
-use bevy::{asset::AssetStage, ecs::schedule::SystemDescriptor, prelude::*};
+use asset::{JsScriptLoader, TranspileErrors};
+use events::{EventSubscriptions, ScriptEventFns, ScriptEventTypes, ScriptEvents};
+use instances::ScriptInstances;
+use std::{
+    cmp::Reverse,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    asset::{AssetPath, AssetStage, HandleId, LoadState},
+    ecs::{
+        event::{Event, ManualEventReader},
+        schedule::{StageLabel, StateData, SystemDescriptor, SystemLabel},
+    },
+    prelude::*,
+    reflect::Enum,
+    time::FixedTimestep,
+    utils::{HashMap, HashSet},
+};
 
 pub use asset::JsScript;
 pub use bevy_ecs_dynamic;
 pub use bevy_reflect_fns;
+pub use instances::JsScriptComponent;
 pub use runtime::{
     ops::ecs::types::{
-        JsReflectFunctions, JsValueRef, JsValueRefKey, JsValueRefs, ReflectFunctionKey,
+        JsEntity, JsReflectFunctions, JsValueRef, JsValueRefKey, JsValueRefs, ReflectFunctionKey,
     },
-    JsRuntimeConfig, JsRuntimeOp, OpContext, OpMap, ScriptInfo,
+    JsRuntime, JsRuntimeApi, JsRuntimeConfig, JsRuntimeOp, JsScriptError, OpContext, OpMap,
+    ScriptInfo,
 };
 pub use serde_json;
 pub use type_map;
 
-use runtime::{JsRuntime, JsRuntimeApi};
-
-#[derive(Default)]
 pub struct JsScriptingPlugin {
     /// By default, the plugin will setup script functions corresponding to each [`CoreStage`] to
-    /// run at the start of each stage. This disables that behavior so that script stages must be
-    /// added manually using [`run_script_fn_system`].
+    /// run at the start of each stage, along with a `fixedUpdate` function run in the
+    /// [`JsFixedUpdateStage`]. This disables that behavior so that script stages must be added
+    /// manually using [`run_script_fn_system`].
     pub skip_core_stage_setup: bool,
+    /// What to do with scripts whose functions throw errors while running.
+    pub error_policy: ScriptErrorPolicy,
+    /// The time in seconds between two runs of the `fixedUpdate` script functions. Defaults to
+    /// 1/60th of a second.
+    pub fixed_timestep: f64,
+}
+
+impl Default for JsScriptingPlugin {
+    fn default() -> Self {
+        Self {
+            skip_core_stage_setup: false,
+            error_policy: default(),
+            fixed_timestep: 1.0 / 60.0,
+        }
+    }
+}
+
+/// Stage running the `fixedUpdate` script functions with a fixed timestep, right before
+/// [`CoreStage::Update`]
+#[derive(StageLabel)]
+pub struct JsFixedUpdateStage;
+
+/// What [`run_script_fn_system`] does when a script function throws an error.
+///
+/// Errors are always logged and sent as [`JsScriptError`] events, regardless of the policy.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScriptErrorPolicy {
+    /// Keep running the script.
+    #[default]
+    Continue,
+    /// Stop running the script once one of its functions has thrown this many errors in a row.
+    ///
+    /// Disabled scripts can be re-enabled with [`ScriptErrorTracker::enable`].
+    DisableAfter(u32),
+    /// Remove the script from the [`ActiveScripts`], or from the entity for the instances of the
+    /// script attached to entities.
+    Unload,
+    /// Panic on the first error, which is useful for tests.
+    Panic,
+}
+
+/// Keeps track of the consecutive errors of the script functions and of the scripts disabled by
+/// [`ScriptErrorPolicy::DisableAfter`].
+///
+/// The instances of a script attached to entities are tracked separately from the script, and from
+/// each other, so they are identified by the script and the entity of the instance, or `None` for
+/// the script itself.
+#[derive(Resource, Default)]
+pub struct ScriptErrorTracker {
+    /// The number of errors in a row thrown by each script function
+    consecutive_errors: HashMap<(Handle<JsScript>, Option<Entity>, String), u32>,
+    disabled: HashSet<(Handle<JsScript>, Option<Entity>)>,
 }
 
+impl ScriptErrorTracker {
+    /// Returns the number of errors in a row thrown by the given script function.
+    pub fn consecutive_errors(
+        &self,
+        handle: &Handle<JsScript>,
+        entity: Option<Entity>,
+        fn_name: &str,
+    ) -> u32 {
+        self.consecutive_errors
+            .get(&(handle.clone_weak(), entity, fn_name.to_owned()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns whether the script has been disabled because of its errors.
+    pub fn is_disabled(&self, handle: &Handle<JsScript>, entity: Option<Entity>) -> bool {
+        self.disabled.contains(&(handle.clone_weak(), entity))
+    }
+
+    /// Re-enables a script disabled because of its errors, and resets its error counts.
+    pub fn enable(&mut self, handle: &Handle<JsScript>, entity: Option<Entity>) {
+        self.disabled.remove(&(handle.clone_weak(), entity));
+        self.reset(handle, entity);
+    }
+
+    /// Disables a script, so that its functions aren't run anymore.
+    pub fn disable(&mut self, handle: &Handle<JsScript>, entity: Option<Entity>) {
+        self.disabled.insert((handle.clone_weak(), entity));
+    }
+
+    /// Resets the error counts of all of the functions of a script.
+    pub fn reset(&mut self, handle: &Handle<JsScript>, entity: Option<Entity>) {
+        self.consecutive_errors
+            .retain(|(script, script_entity, _), _| script != handle || *script_entity != entity);
+    }
+
+    /// Records the result of running a script function, and returns its number of errors in a
+    /// row.
+    fn record(
+        &mut self,
+        handle: &Handle<JsScript>,
+        entity: Option<Entity>,
+        fn_name: &str,
+        is_error: bool,
+    ) -> u32 {
+        let key = (handle.clone_weak(), entity, fn_name.to_owned());
+        if is_error {
+            let errors = self.consecutive_errors.entry(key).or_default();
+            *errors += 1;
+            *errors
+        } else {
+            self.consecutive_errors.remove(&key);
+            0
+        }
+    }
+
+    /// Forgets the errors of the instance of a script that has been removed from its entity.
+    fn remove_instance(&mut self, handle: &Handle<JsScript>, entity: Entity) {
+        self.disabled.remove(&(handle.clone_weak(), Some(entity)));
+        self.reset(handle, Some(entity));
+    }
+}
+
+/// The scripts whose stage functions are run by [`run_script_fn_system`], along with the settings
+/// controlling when and in which order they run.
 #[derive(Resource, Default, Deref, DerefMut)]
-pub struct ActiveScripts(pub indexmap::IndexSet<Handle<JsScript>, bevy::utils::FixedState>);
+pub struct ActiveScripts(
+    pub indexmap::IndexMap<Handle<JsScript>, JsScriptSettings, bevy::utils::FixedState>,
+);
+
+/// A condition that must be true for the stage functions of a script to run
+pub type ScriptRunCriteria = Arc<dyn Fn(&World) -> bool + Send + Sync>;
+
+/// Settings controlling when and in which order the stage functions of an active script are run.
+///
+/// Scripts run in the order they were added in, unless ordered otherwise by their
+/// [`priority`][Self::priority] or by [`before`][Self::before] and [`after`][Self::after]
+/// constraints.
+#[derive(Clone)]
+pub struct JsScriptSettings {
+    /// Whether the stage functions of the script are run
+    pub enabled: bool,
+    /// Scripts with a higher priority run before scripts with a lower priority, as long as that
+    /// doesn't break any `before` or `after` constraints. Defaults to `0`.
+    pub priority: i32,
+    /// Scripts that this script must run before
+    pub before: Vec<Handle<JsScript>>,
+    /// Scripts that this script must run after
+    pub after: Vec<Handle<JsScript>>,
+    /// If set, the stage functions of the script are only run when this returns `true`
+    pub run_criteria: Option<ScriptRunCriteria>,
+}
+
+impl Default for JsScriptSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            priority: 0,
+            before: Vec::new(),
+            after: Vec::new(),
+            run_criteria: None,
+        }
+    }
+}
+
+impl JsScriptSettings {
+    /// Sets the [`priority`][Self::priority] of the script.
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Makes the script run before the script at the given asset path.
+    pub fn before(mut self, path: &str) -> Self {
+        self.before.push(Handle::weak(AssetPath::from(path).into()));
+        self
+    }
+
+    /// Makes the script run after the script at the given asset path.
+    pub fn after(mut self, path: &str) -> Self {
+        self.after.push(Handle::weak(AssetPath::from(path).into()));
+        self
+    }
+
+    /// Only runs the stage functions of the script when `run_criteria` returns `true`.
+    pub fn run_if(mut self, run_criteria: impl Fn(&World) -> bool + Send + Sync + 'static) -> Self {
+        self.run_criteria = Some(Arc::new(run_criteria));
+        self
+    }
+
+    /// Sets whether the script is [`enabled`][Self::enabled].
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+}
+
+impl ActiveScripts {
+    /// Returns the active scripts in the order their stage functions should run in, and whether
+    /// the `before` and `after` constraints of the scripts contain a cycle.
+    ///
+    /// When there is a cycle, the scripts in the cycle are ordered by priority only.
+    pub fn run_order(&self) -> (Vec<Handle<JsScript>>, bool) {
+        // Whether the script at index `a` must run before the script at index `b`
+        let must_run_before = |a: usize, b: usize| {
+            let (a_handle, a_settings) = self.get_index(a).unwrap();
+            let (b_handle, b_settings) = self.get_index(b).unwrap();
+            a_settings.before.contains(b_handle) || b_settings.after.contains(a_handle)
+        };
+
+        let mut remaining = (0..self.len()).collect::<Vec<_>>();
+        let mut order = Vec::with_capacity(self.len());
+        let mut has_cycle = false;
+
+        while !remaining.is_empty() {
+            // Scripts that don't need to wait for any of the remaining scripts
+            let ready = remaining
+                .iter()
+                .copied()
+                .filter(|&idx| !remaining.iter().any(|&other| must_run_before(other, idx)))
+                .collect::<Vec<_>>();
+            has_cycle |= ready.is_empty();
+
+            // Pick the script with the highest priority, preferring the earliest added script
+            let candidates = if ready.is_empty() { &remaining } else { &ready };
+            let next = candidates
+                .iter()
+                .copied()
+                .min_by_key(|&idx| (Reverse(self[idx].priority), idx))
+                .unwrap();
+
+            remaining.retain(|&idx| idx != next);
+            order.push(self.get_index(next).unwrap().0.clone_weak());
+        }
+
+        (order, has_cycle)
+    }
+}
 
 impl Plugin for JsScriptingPlugin {
     fn build(&self, app: &mut App) {
+        let transpile_errors = TranspileErrors::default();
+
         app.init_non_send_resource::<JsRuntime>()
             .init_resource::<ActiveScripts>()
+            .init_resource::<ScriptErrorTracker>()
+            .init_resource::<InitializedScripts>()
+            .init_resource::<StartedScripts>()
+            .init_resource::<ScriptInstances>()
+            .init_resource::<SeparateScriptFns>()
+            .init_resource::<EventSubscriptions>()
+            .init_resource::<ScriptEvents>()
+            .init_resource::<ScriptEventTypes>()
+            .register_type::<JsScriptComponent>()
+            .insert_resource(self.error_policy)
+            .insert_resource(transpile_errors.clone())
+            .add_event::<JsScriptError>()
             .add_asset::<JsScript>()
-            .add_asset_loader(JsScriptLoader)
+            .add_asset_loader(JsScriptLoader { transpile_errors })
             .add_system_to_stage(
                 AssetStage::AssetEvents,
                 load_scripts.after(Assets::<JsScript>::asset_event_system),
+            )
+            .add_system_to_stage(AssetStage::AssetEvents, send_transpile_errors)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                events::dispatch_script_events.at_end(),
             );
 
         // Call runtime `frame_start()` and `frame_end()` functions at the beginning and end of each frame.
@@ -52,7 +318,15 @@ impl Plugin for JsScriptingPlugin {
                 runtime.frame_start(world);
                 world.insert_non_send_resource(runtime);
             })
-            .at_start(),
+            .at_start()
+            .label(JsFrameStartLabel),
+        )
+        .add_system_to_stage(
+            CoreStage::First,
+            run_startup_fns
+                .at_start()
+                .label(JsStartupLabel)
+                .after(JsFrameStartLabel),
         )
         .add_system_to_stage(
             CoreStage::Last,
@@ -73,47 +347,396 @@ impl Plugin for JsScriptingPlugin {
                 (CoreStage::PostUpdate, "postUpdate"),
                 (CoreStage::Last, "last"),
             ] {
-                app.add_system_to_stage(label, run_script_fn_system(fn_name.to_owned()).at_start());
+                let system = run_script_fn_system(fn_name.to_owned()).at_start();
+                // The `startup` functions run before anything else in the frame
+                let system = if fn_name == "first" {
+                    system.after(JsStartupLabel)
+                } else {
+                    system
+                };
+                app.add_system_to_stage(label, system);
             }
+
+            // Run the `fixedUpdate` script functions with a fixed timestep
+            app.add_stage_before(
+                CoreStage::Update,
+                JsFixedUpdateStage,
+                SystemStage::parallel()
+                    .with_run_criteria(FixedTimestep::step(self.fixed_timestep))
+                    .with_system(run_script_fn_system("fixedUpdate".to_owned())),
+            );
+        }
+    }
+}
+
+/// Label of the systems running the script function with the given name, which includes the
+/// systems returned by [`run_script_fn_system`] and the ones added by the plugin for the core stages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JsStageFnLabel(&'static str);
+
+impl JsStageFnLabel {
+    pub fn new(fn_name: &str) -> Self {
+        Self(leak_label_str(fn_name))
+    }
+}
+
+impl SystemLabel for JsStageFnLabel {
+    fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+/// Label of the system running the script function with the given name for a single script,
+/// added with [`AddJsSystem::add_js_script_fn_with`]
+///
+/// This lets the function of one script be ordered relative to the functions of other scripts, or
+/// between specific Rust systems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JsScriptLabel(pub HandleId, pub &'static str);
+
+impl JsScriptLabel {
+    /// Returns the label of the function `fn_name` of the script at the given asset path.
+    pub fn new(path: &str, fn_name: &str) -> Self {
+        Self(AssetPath::from(path).into(), leak_label_str(fn_name))
+    }
+}
+
+impl SystemLabel for JsScriptLabel {
+    fn as_str(&self) -> &'static str {
+        leak_label_str(&format!("{:?}.{}", self.0, self.1))
+    }
+}
+
+/// Returns a static version of a label name. Labels need static strings, so the names are
+/// leaked, but only once per name.
+fn leak_label_str(name: &str) -> &'static str {
+    static NAMES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+    let mut names = NAMES.lock().unwrap();
+    match names.iter().find(|x| **x == name) {
+        Some(name) => name,
+        None => {
+            let leaked = &*Box::leak(name.to_owned().into_boxed_str());
+            names.push(leaked);
+            leaked
+        }
+    }
+}
+
+/// The script functions run by systems of their own, which the systems running the functions for
+/// all of the active scripts skip
+#[derive(Resource, Default, Deref, DerefMut)]
+pub(crate) struct SeparateScriptFns(HashSet<(Handle<JsScript>, String)>);
+
+impl SeparateScriptFns {
+    /// Returns whether the system running the function `fn_name` for the given script, or for all
+    /// of the scripts if `system_script` is `None`, should run the function of `script`.
+    pub(crate) fn runs_script_fn(
+        &self,
+        system_script: Option<&Handle<JsScript>>,
+        script: &Handle<JsScript>,
+        fn_name: &str,
+    ) -> bool {
+        match system_script {
+            Some(system_script) => system_script == script,
+            None => !self.contains(&(script.clone_weak(), fn_name.to_owned())),
         }
     }
 }
 
 /// This returns a system that will run the exported function, `fn_name`, for every script in
-/// [`ActiveScripts`].
+/// [`ActiveScripts`], and then for every instance of the scripts attached to entities with a
+/// [`JsScriptComponent`].
 ///
 /// This allows you to schedule which stages different script functions will be executed at.
 ///
 /// By default the plugin will run script functions corresponding to Bevy [`CoreStage`]s at the
 /// start of each core stage, but this can be disabled by setting
 /// [`JsCriptingPlugin::skip_core_stage_setup`] to `true`.
+///
+/// Errors thrown by the script functions are handled according to the [`ScriptErrorPolicy`].
+///
+/// The system is labeled with the [`JsStageFnLabel`] of `fn_name`.
 pub fn run_script_fn_system(fn_name: String) -> SystemDescriptor {
-    (move |world: &mut World| {
-        let active_scripts = world.remove_resource::<ActiveScripts>().unwrap();
+    let label = JsStageFnLabel::new(&fn_name);
+    script_fn_system(fn_name, None).label(label)
+}
+
+/// Returns a system running the exported function `fn_name` of the active scripts and their
+/// instances, or only of `system_script` if it is set.
+fn script_fn_system(
+    fn_name: String,
+    system_script: Option<Handle<JsScript>>,
+) -> impl FnMut(&mut World) + Send + Sync + 'static {
+    let mut reported_cycle = false;
+    // The run order of the active scripts, which is only recomputed when they change
+    let mut run_order: Option<Vec<Handle<JsScript>>> = None;
+    move |world: &mut World| {
+        // The active scripts are left in the world, so that they are only detected as changed
+        // when they actually are.
+        if run_order.is_none() || world.is_resource_changed::<ActiveScripts>() {
+            let (order, has_cycle) = world.resource::<ActiveScripts>().run_order();
+            if has_cycle && !reported_cycle {
+                warn!("The before and after constraints of the active scripts contain a cycle");
+                reported_cycle = true;
+            }
+            run_order = Some(order);
+        }
+
+        let mut error_tracker = world.remove_resource::<ScriptErrorTracker>().unwrap();
+        let error_policy = *world.resource::<ScriptErrorPolicy>();
         let runtime = world.remove_non_send_resource::<JsRuntime>().unwrap();
 
-        for script in &*active_scripts {
-            if runtime.has_loaded(script) {
-                runtime.run_script(script, &fn_name, world);
+        let separate_fns = world.resource::<SeparateScriptFns>();
+        let scripts = run_order
+            .iter()
+            .flatten()
+            .filter(|handle| separate_fns.runs_script_fn(system_script.as_ref(), handle, &fn_name))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut scripts_to_unload = Vec::new();
+        for script in &scripts {
+            let (enabled, run_criteria) = match world.resource::<ActiveScripts>().get(script) {
+                Some(settings) => (settings.enabled, settings.run_criteria.clone()),
+                None => continue,
+            };
+            if !enabled
+                || error_tracker.is_disabled(script, None)
+                || !runtime.has_loaded(script)
+                || !run_criteria.map_or(true, |run_criteria| run_criteria(world))
+            {
+                continue;
+            }
+
+            let result = runtime.run_script(script, &fn_name, &[], world);
+            let errors = error_tracker.record(script, None, &fn_name, result.is_err());
+
+            if let Err(error) = result {
+                error!("{error}");
+                if handle_script_error(
+                    error_policy,
+                    &mut error_tracker,
+                    script,
+                    None,
+                    errors,
+                    &error,
+                ) {
+                    scripts_to_unload.push(script.clone_weak());
+                }
+                world.send_event(error);
             }
         }
 
-        world.insert_resource(active_scripts);
+        if !scripts_to_unload.is_empty() {
+            let mut active_scripts = world.resource_mut::<ActiveScripts>();
+            for script in &scripts_to_unload {
+                active_scripts.shift_remove(script);
+            }
+        }
+
+        instances::run_instances_fn(
+            &runtime,
+            &fn_name,
+            system_script.as_ref(),
+            error_policy,
+            &mut error_tracker,
+            world,
+        );
+        world.insert_resource(error_tracker);
         world.insert_non_send_resource(runtime);
-    })
-    .into_descriptor()
+    }
+}
+
+/// Applies the error policy to an error thrown by a function of a script, or of its instance for
+/// `entity`, which has thrown `errors` errors in a row. Returns whether the script should be
+/// unloaded.
+pub(crate) fn handle_script_error(
+    error_policy: ScriptErrorPolicy,
+    error_tracker: &mut ScriptErrorTracker,
+    script: &Handle<JsScript>,
+    entity: Option<Entity>,
+    errors: u32,
+    error: &JsScriptError,
+) -> bool {
+    match error_policy {
+        ScriptErrorPolicy::Continue => false,
+        ScriptErrorPolicy::DisableAfter(max_errors) => {
+            if errors >= max_errors {
+                warn!(?error.path, "Disabling script after {errors} errors in a row");
+                error_tracker.disable(script, entity);
+            }
+            false
+        }
+        ScriptErrorPolicy::Unload => {
+            warn!(?error.path, "Unloading script after error");
+            true
+        }
+        ScriptErrorPolicy::Panic => panic!("{error}"),
+    }
 }
 
 pub trait AddJsSystem {
     fn add_js_system(&mut self, path: &str) -> &mut Self;
+
+    /// Runs the exported function `fn_name` of the active scripts in the given stage, using
+    /// [`run_script_fn_system`].
+    fn add_js_stage_fn(&mut self, fn_name: &str, stage: impl StageLabel) -> &mut Self {
+        self.add_js_stage_fn_with(fn_name, stage, |system| system)
+    }
+
+    /// Runs the exported function `fn_name` of the active scripts in the given stage, letting
+    /// `configure` add labels and ordering constraints to the system running it:
+    ///
+    /// ```ignore
+    /// app.add_js_stage_fn_with("physicsStep", PhysicsStage, |system| {
+    ///     system.after(step_physics).label(ScriptPhysics)
+    /// });
+    /// ```
+    ///
+    /// Script functions are run by exclusive systems, so they can only be ordered relative to
+    /// other exclusive systems.
+    fn add_js_stage_fn_with(
+        &mut self,
+        fn_name: &str,
+        stage: impl StageLabel,
+        configure: impl FnOnce(SystemDescriptor) -> SystemDescriptor,
+    ) -> &mut Self;
+
+    /// Runs the functions of the active scripts for the given value of the [`State<S>`], from the
+    /// [`SystemSet::on_enter`], [`SystemSet::on_exit`] and [`SystemSet::on_update`] system sets.
+    ///
+    /// Scripts provide these functions through exported `onEnter`, `onExit` and `onUpdate`
+    /// objects, keyed by the variant name of the state value, without the fields of the variant:
+    ///
+    /// ```js
+    /// export const onEnter = {
+    ///     Playing() { info("Started playing"); },
+    /// };
+    /// ```
+    ///
+    /// The state must be added to the app with [`App::add_state`], and this needs to be called for
+    /// each of the state values that scripts respond to.
+    fn add_js_state<S: StateData + Enum>(&mut self, state: S) -> &mut Self;
+
+    /// Lets scripts handle the events of type `E` by registering handlers with `events.on()`:
+    ///
+    /// ```js
+    /// events.on(CollisionEvent, (event) => info("Collision:", event.toString()));
+    /// ```
+    ///
+    /// The events sent before [`CoreStage::PostUpdate`] are passed to the handlers at the end of
+    /// that stage. Scripts can also read the events with `world.events(CollisionEvent).read()`,
+    /// and send events with `world.sendEvent(CollisionEvent, value)`.
+    fn add_js_event<E: Event + FromReflect>(&mut self) -> &mut Self;
+
+    /// Runs the exported function `fn_name` of the script at the given asset path, and of its
+    /// instances, in a system of its own in the given stage. The systems running the function for
+    /// all of the active scripts skip the script.
+    ///
+    /// The system is labeled with the [`JsScriptLabel`] of the script and function, along with
+    /// the [`JsStageFnLabel`] of the function, and `configure` can add ordering constraints:
+    ///
+    /// ```ignore
+    /// app.add_js_script_fn_with("scripts/ai.ts", "update", CoreStage::Update, |system| {
+    ///     system.before(JsScriptLabel::new("scripts/movement.ts", "update"))
+    /// });
+    /// ```
+    ///
+    /// Like for the other active scripts, the function only runs while the script is one of the
+    /// [`ActiveScripts`].
+    fn add_js_script_fn_with(
+        &mut self,
+        path: &str,
+        fn_name: &str,
+        stage: impl StageLabel,
+        configure: impl FnOnce(SystemDescriptor) -> SystemDescriptor,
+    ) -> &mut Self;
+
+    /// Adds a script to the [`ActiveScripts`] with the given settings.
+    fn add_js_system_with(&mut self, path: &str, settings: JsScriptSettings) -> &mut Self;
+
+    /// Removes a script added with [`add_js_system`][Self::add_js_system] from the
+    /// [`ActiveScripts`], which runs its `onUnload` hook and unloads it from the runtime.
+    ///
+    /// At runtime, scripts can be removed from the [`ActiveScripts`] resource directly.
+    fn remove_js_system(&mut self, path: &str) -> &mut Self;
 }
 impl AddJsSystem for App {
     fn add_js_system(&mut self, path: &str) -> &mut Self {
+        self.add_js_system_with(path, default())
+    }
+
+    fn add_js_stage_fn_with(
+        &mut self,
+        fn_name: &str,
+        stage: impl StageLabel,
+        configure: impl FnOnce(SystemDescriptor) -> SystemDescriptor,
+    ) -> &mut Self {
+        self.add_system_to_stage(stage, configure(run_script_fn_system(fn_name.to_owned())))
+    }
+
+    fn add_js_script_fn_with(
+        &mut self,
+        path: &str,
+        fn_name: &str,
+        stage: impl StageLabel,
+        configure: impl FnOnce(SystemDescriptor) -> SystemDescriptor,
+    ) -> &mut Self {
+        let handle = Handle::<JsScript>::weak(AssetPath::from(path).into());
+        self.world
+            .resource_mut::<SeparateScriptFns>()
+            .insert((handle.clone_weak(), fn_name.to_owned()));
+
+        let system = script_fn_system(fn_name.to_owned(), Some(handle))
+            .label(JsScriptLabel::new(path, fn_name))
+            .label(JsStageFnLabel::new(fn_name));
+        self.add_system_to_stage(stage, configure(system))
+    }
+
+    fn add_js_state<S: StateData + Enum>(&mut self, state: S) -> &mut Self {
+        let key = state.variant_name().to_owned();
+        self.add_system_set(
+            SystemSet::on_enter(state.clone())
+                .with_system(run_script_fn_system(format!("onEnter.{key}"))),
+        )
+        .add_system_set(
+            SystemSet::on_exit(state.clone())
+                .with_system(run_script_fn_system(format!("onExit.{key}"))),
+        )
+        .add_system_set(
+            SystemSet::on_update(state)
+                .with_system(run_script_fn_system(format!("onUpdate.{key}"))),
+        )
+    }
+
+    fn add_js_event<E: Event + FromReflect>(&mut self) -> &mut Self {
+        let type_name = std::any::type_name::<E>();
+        self.world
+            .resource_mut::<ScriptEventTypes>()
+            .insert(type_name.to_owned(), ScriptEventFns::new::<E>());
+        self.world
+            .resource_mut::<ScriptEvents>()
+            .insert(type_name.to_owned(), Vec::new());
+        self.add_system_to_stage(CoreStage::PostUpdate, events::collect_script_events::<E>)
+    }
+
+    fn add_js_system_with(&mut self, path: &str, settings: JsScriptSettings) -> &mut Self {
         let asset_server = self.world.resource::<AssetServer>();
         let handle = asset_server.load(path);
 
         let mut active = self.world.resource_mut::<ActiveScripts>();
-        active.insert(handle);
+        active.insert(handle, settings);
+
+        self
+    }
+
+    fn remove_js_system(&mut self, path: &str) -> &mut Self {
+        let asset_server = self.world.resource::<AssetServer>();
+        let handle: Handle<JsScript> = asset_server.get_handle(path);
+
+        let mut active = self.world.resource_mut::<ActiveScripts>();
+        active.shift_remove(&handle);
 
         self
     }
@@ -125,28 +748,102 @@ struct ScriptToLoad {
     reload: bool,
 }
 
-/// System to finish loading scripts that have had their source-code loaded by the asset server.
+/// The [`ActiveScripts`] that have had their `init` hook run.
+#[derive(Resource, Default, Deref, DerefMut)]
+struct InitializedScripts(HashSet<Handle<JsScript>>);
+
+/// The [`ActiveScripts`] that have had their `startup` function run.
+#[derive(Resource, Default, Deref, DerefMut)]
+struct StartedScripts(HashSet<Handle<JsScript>>);
+
+/// Label of the system calling [`JsRuntimeApi::frame_start`]
+#[derive(SystemLabel)]
+struct JsFrameStartLabel;
+
+/// Label of the system running the `startup` functions of the scripts at the start of the frame
+#[derive(SystemLabel)]
+struct JsStartupLabel;
+
+/// System to finish loading scripts that have had their source-code loaded by the asset server,
+/// and to run the lifecycle hooks of the scripts.
+///
+/// Scripts are only loaded after all of the scripts they import have been loaded, except for scripts
+/// importing each other, which are loaded together. Scripts importing a script that failed to load
+/// fail to load as well. When a script is modified, all of the scripts that depend on it are
+/// reloaded along with it.
 fn load_scripts(
+    world: &mut World,
+    mut event_reader: Local<ManualEventReader<AssetEvent<JsScript>>>,
     mut scripts_to_load: Local<Vec<ScriptToLoad>>,
-    mut events: EventReader<AssetEvent<JsScript>>,
-    assets: Res<Assets<JsScript>>,
-    engine: NonSendMut<JsRuntime>,
+    mut unloaded_scripts: Local<HashSet<Handle<JsScript>>>,
 ) {
-    for event in events.iter() {
+    let runtime = world.remove_non_send_resource::<JsRuntime>().unwrap();
+
+    // Helper to queue a script, without queuing it twice
+    let mut queue_script = |handle: &Handle<JsScript>, reload: bool| {
+        if let Some(queued) = scripts_to_load.iter_mut().find(|x| &x.handle == handle) {
+            queued.reload |= reload;
+        } else {
+            scripts_to_load.push(ScriptToLoad {
+                handle: handle.clone_weak(),
+                reload,
+            });
+        }
+    };
+
+    let mut removed_scripts = Vec::new();
+    let assets = world.resource::<Assets<JsScript>>();
+    let events = world.resource::<Events<AssetEvent<JsScript>>>();
+    let active_scripts = world.resource::<ActiveScripts>();
+    for event in event_reader.iter(events) {
         match event {
             AssetEvent::Created { handle } => {
-                scripts_to_load.push(ScriptToLoad {
-                    handle: handle.clone_weak(),
-                    reload: false,
-                });
+                queue_script(handle, false);
+
+                // Active scripts that failed to load because they import this script can be
+                // loaded now.
+                for dependent in dependent_scripts(assets, handle) {
+                    if active_scripts.contains_key(&dependent) && !runtime.has_loaded(&dependent) {
+                        queue_script(&dependent, false);
+                    }
+                }
             }
             AssetEvent::Modified { handle } => {
-                scripts_to_load.push(ScriptToLoad {
-                    handle: handle.clone_weak(),
-                    reload: true,
-                });
+                queue_script(handle, true);
+
+                // Scripts importing the modified script need to be re-evaluated to pick up the
+                // new version of its exports.
+                for dependent in dependent_scripts(assets, handle) {
+                    queue_script(&dependent, true);
+                }
             }
-            _ => (),
+            AssetEvent::Removed { handle } => removed_scripts.push(handle.clone_weak()),
+        }
+    }
+
+    // Scripts added back to the active scripts after being unloaded need to be loaded again
+    unloaded_scripts.retain(|handle| {
+        let is_active = active_scripts.contains_key(handle);
+        if is_active {
+            queue_script(handle, false);
+        }
+        !is_active
+    });
+
+    // Unload the scripts whose assets have been removed, along with the loaded scripts importing
+    // them, which are loaded again if the assets come back.
+    for handle in &removed_scripts {
+        scripts_to_load.retain(|x| &x.handle != handle);
+        unloaded_scripts.remove(handle);
+
+        let dependents = dependent_scripts(world.resource::<Assets<JsScript>>(), handle);
+        let loaded_dependents = dependents
+            .into_iter()
+            .filter(|dependent| runtime.has_loaded(dependent));
+        for script in loaded_dependents.chain([handle.clone_weak()]) {
+            world.resource_mut::<StartedScripts>().remove(&script);
+            deinitialize_script(&runtime, &script, world);
+            unload_script(&runtime, &script, world);
         }
     }
 
@@ -154,16 +851,418 @@ fn load_scripts(
     let mut scripts = Vec::new();
     std::mem::swap(&mut *scripts_to_load, &mut scripts);
 
-    for to_load in scripts {
-        // If the script asset has loaded
-        if let Some(script) = assets.get(&to_load.handle) {
-            // Have the engine load the script
-            engine.load_script(&to_load.handle, script, to_load.reload);
+    // Queue the imports of the scripts that are loaded as assets but not by the runtime, such as
+    // scripts that were unloaded when removed from the active scripts.
+    let assets = world.resource::<Assets<JsScript>>();
+    let mut idx = 0;
+    while idx < scripts.len() {
+        if let Some(script) = assets.get(&scripts[idx].handle) {
+            for dependency in script.dependency_handles() {
+                if assets.contains(&dependency)
+                    && !runtime.has_loaded(&dependency)
+                    && !scripts.iter().any(|x| x.handle == dependency)
+                {
+                    scripts.push(ScriptToLoad {
+                        handle: dependency,
+                        reload: false,
+                    });
+                }
+            }
+        }
+        idx += 1;
+    }
+
+    // Scripts that failed to load in this pass, so that the scripts importing them can't be
+    // loaded either
+    let mut failed_scripts = HashSet::new();
+
+    loop {
+        fail_scripts_with_failed_imports(&mut scripts, &mut failed_scripts, &runtime, world);
+
+        // Load the next script whose imports have been loaded, along with the queued scripts it
+        // imports.
+        let assets = world.resource::<Assets<JsScript>>();
+        let group = match next_script_group(&scripts, assets, &runtime) {
+            Some(group) => group,
+            None => break,
+        };
+
+        for handle in &group {
+            let to_load = match scripts.iter().position(|x| &x.handle == handle) {
+                Some(idx) => scripts.remove(idx),
+                // The script was dropped because one of its imports failed to reload
+                None => continue,
+            };
+
+            // Scripts importing each other are loaded in the same group, so an import may have
+            // failed since the group was put together.
+            let assets = world.resource::<Assets<JsScript>>();
+            let script = assets.get(handle).unwrap();
+            if let Some(error) = failed_import_error(handle, script, &failed_scripts, &runtime) {
+                failed_scripts.insert(handle.clone_weak());
+                error!("{error}");
+                world.send_event(error);
+                continue;
+            }
+
+            // Save the state of the previous version of the script, so it can be restored in the
+            // new version.
+            let saved_state = if to_load.reload {
+                save_state(&runtime, handle, world)
+            } else {
+                None
+            };
+
+            // Initialized scripts get to see the exports of their previous version when reloaded
+            let previous_state = (to_load.reload
+                && world.resource::<InitializedScripts>().contains(handle))
+            .then(|| runtime.snapshot_exports(handle));
+
+            // Have the runtime load the script
+            let first_subscription = world.resource::<EventSubscriptions>().next_id();
+            match runtime.load_script(handle, to_load.reload, world) {
+                Ok(()) => {
+                    // Drop the event handlers registered by the previous version of the script.
+                    // Instances keep theirs, along with the rest of their state.
+                    if to_load.reload {
+                        events::remove_event_handlers(
+                            &runtime,
+                            handle,
+                            |id, subscription| {
+                                subscription.entity.is_none() && id < first_subscription
+                            },
+                            world,
+                        );
+                    }
+
+                    if let Some(saved_state) = saved_state {
+                        run_hook(&runtime, handle, "restoreState", &[saved_state], world);
+                    }
+                    if let Some(previous_state) = previous_state {
+                        run_hook(&runtime, handle, "onReload", &[previous_state], world);
+                    }
+                }
+                Err(error) => {
+                    // Drop the event handlers registered before the script failed
+                    events::remove_event_handlers(
+                        &runtime,
+                        handle,
+                        |id, _| id >= first_subscription,
+                        world,
+                    );
+
+                    if to_load.reload {
+                        // The previous version of the script keeps running when it fails to
+                        // reload, so the scripts importing it don't need to be reloaded either.
+                        let dependents =
+                            dependent_scripts(world.resource::<Assets<JsScript>>(), handle);
+                        scripts.retain(|x| !(x.reload && dependents.contains(&x.handle)));
+                    } else {
+                        failed_scripts.insert(handle.clone_weak());
+                    }
+
+                    error!("{error}");
+                    world.send_event(error);
+                }
+            }
+        }
+    }
 
-        // If the asset hasn't loaded yet
+    // Try to load the remaining scripts later
+    *scripts_to_load = scripts;
+
+    run_lifecycle_hooks(&runtime, &mut unloaded_scripts, world);
+    instances::update_script_instances(&runtime, world);
+
+    world.insert_non_send_resource(runtime);
+}
+
+/// Returns the next group of queued scripts to load: a queued script along with the queued scripts
+/// it imports, directly or indirectly, with the imported scripts coming first.
+///
+/// Scripts importing each other end up in the same group, which is needed for them to be loaded at
+/// all. Returns `None` if none of the queued scripts can be loaded yet, because they haven't been
+/// loaded as assets or import a script that isn't loaded.
+fn next_script_group(
+    scripts: &[ScriptToLoad],
+    assets: &Assets<JsScript>,
+    runtime: &JsRuntime,
+) -> Option<Vec<Handle<JsScript>>> {
+    scripts.iter().find_map(|to_load| {
+        let mut group = Vec::new();
+        add_to_script_group(&to_load.handle, scripts, assets, runtime, &mut group).then_some(group)
+    })
+}
+
+/// Adds a queued script to a group of scripts to load after the queued scripts it imports, and
+/// returns whether all of them can be loaded.
+fn add_to_script_group(
+    handle: &Handle<JsScript>,
+    scripts: &[ScriptToLoad],
+    assets: &Assets<JsScript>,
+    runtime: &JsRuntime,
+    group: &mut Vec<Handle<JsScript>>,
+) -> bool {
+    // Scripts already in the group are either added or being added, in case of an import cycle
+    if group.contains(handle) {
+        return true;
+    }
+    let script = match assets.get(handle) {
+        Some(script) => script,
+        None => return false,
+    };
+
+    let start = group.len();
+    group.push(handle.clone_weak());
+    for dependency in script.dependency_handles() {
+        let can_load = if scripts.iter().any(|x| x.handle == dependency) {
+            add_to_script_group(&dependency, scripts, assets, runtime, group)
         } else {
-            // Add it to the list of scripts to try to load later
-            scripts_to_load.push(to_load);
+            runtime.has_loaded(&dependency)
+        };
+        if !can_load {
+            return false;
+        }
+    }
+
+    // Move the script after its imports
+    let handle = group.remove(start);
+    group.push(handle);
+
+    true
+}
+
+/// Removes the queued scripts importing scripts that failed to load from the queue, and reports
+/// the failures.
+///
+/// Scripts fail to load when they can't be transpiled, or when they throw while being evaluated.
+fn fail_scripts_with_failed_imports(
+    scripts: &mut Vec<ScriptToLoad>,
+    failed_scripts: &mut HashSet<Handle<JsScript>>,
+    runtime: &JsRuntime,
+    world: &mut World,
+) {
+    let assets = world.resource::<Assets<JsScript>>();
+    let asset_server = world.resource::<AssetServer>();
+
+    // Scripts that failed to transpile
+    for to_load in scripts.iter() {
+        if let Some(script) = assets.get(&to_load.handle) {
+            for dependency in script.dependency_handles() {
+                if asset_server.get_load_state(&dependency) == LoadState::Failed
+                    && !runtime.has_loaded(&dependency)
+                {
+                    failed_scripts.insert(dependency);
+                }
+            }
+        }
+    }
+
+    // Keep failing scripts until the scripts importing the failed ones have failed as well
+    loop {
+        let assets = world.resource::<Assets<JsScript>>();
+        let failed = scripts.iter().enumerate().find_map(|(idx, to_load)| {
+            let script = assets.get(&to_load.handle)?;
+            failed_import_error(&to_load.handle, script, failed_scripts, runtime)
+                .map(|error| (idx, error))
+        });
+        let (idx, error) = match failed {
+            Some(failed) => failed,
+            None => break,
+        };
+
+        failed_scripts.insert(scripts.remove(idx).handle);
+        error!("{error}");
+        world.send_event(error);
+    }
+}
+
+/// Returns the error reported for a script when one of the scripts it imports failed to load.
+fn failed_import_error(
+    handle: &Handle<JsScript>,
+    script: &JsScript,
+    failed_scripts: &HashSet<Handle<JsScript>>,
+    runtime: &JsRuntime,
+) -> Option<JsScriptError> {
+    script
+        .dependencies
+        .iter()
+        .zip(script.dependency_handles())
+        .find(|(_, dependency)| {
+            failed_scripts.contains(dependency) && !runtime.has_loaded(dependency)
+        })
+        .map(|(path, _)| JsScriptError {
+            handle: handle.clone_weak(),
+            path: script.path.clone(),
+            stage_fn: None,
+            message: format!("Imported script {path:?} failed to load"),
+            stack: None,
+        })
+}
+
+/// Runs the `init` hook of the [`ActiveScripts`] that have been loaded, and unloads the scripts
+/// that have been removed from the [`ActiveScripts`] after running their `onUnload` hook.
+///
+/// Removed scripts that are imported by other loaded scripts stay loaded, and the others are added
+/// to `unloaded_scripts`.
+fn run_lifecycle_hooks(
+    runtime: &JsRuntime,
+    unloaded_scripts: &mut HashSet<Handle<JsScript>>,
+    world: &mut World,
+) {
+    let active_scripts = world.resource::<ActiveScripts>();
+    let removed_scripts = world
+        .resource::<InitializedScripts>()
+        .iter()
+        .filter(|handle| !active_scripts.contains_key(*handle))
+        .cloned()
+        .collect::<Vec<_>>();
+    for handle in &removed_scripts {
+        deinitialize_script(runtime, handle, world);
+
+        let is_imported = dependent_scripts(world.resource::<Assets<JsScript>>(), handle)
+            .iter()
+            .any(|dependent| runtime.has_loaded(dependent));
+        if !is_imported {
+            unload_script(runtime, handle, world);
+            unloaded_scripts.insert(handle.clone_weak());
         }
     }
+
+    let initialized_scripts = world.resource::<InitializedScripts>();
+    let new_scripts = world
+        .resource::<ActiveScripts>()
+        .keys()
+        .filter(|handle| runtime.has_loaded(handle) && !initialized_scripts.contains(*handle))
+        .map(Handle::clone_weak)
+        .collect::<Vec<_>>();
+    for handle in &new_scripts {
+        world
+            .resource_mut::<InitializedScripts>()
+            .insert(handle.clone_weak());
+        run_hook(runtime, handle, "init", &[], world);
+    }
+}
+
+/// Runs the `startup` function of the [`ActiveScripts`] that have been loaded and enabled since the
+/// last frame, once per script.
+fn run_startup_fns(world: &mut World) {
+    let runtime = world.remove_non_send_resource::<JsRuntime>().unwrap();
+
+    let started_scripts = world.resource::<StartedScripts>();
+    let scripts_to_start = world
+        .resource::<ActiveScripts>()
+        .iter()
+        .filter(|(handle, settings)| {
+            settings.enabled && runtime.has_loaded(handle) && !started_scripts.contains(*handle)
+        })
+        .map(|(handle, _)| handle.clone_weak())
+        .collect::<Vec<_>>();
+    for handle in &scripts_to_start {
+        world
+            .resource_mut::<StartedScripts>()
+            .insert(handle.clone_weak());
+        run_hook(&runtime, handle, "startup", &[], world);
+    }
+
+    world.insert_non_send_resource(runtime);
+}
+
+/// Runs the `onUnload` hook of a script if it has been initialized.
+fn deinitialize_script(runtime: &JsRuntime, handle: &Handle<JsScript>, world: &mut World) {
+    if world.resource_mut::<InitializedScripts>().remove(handle) {
+        run_hook(runtime, handle, "onUnload", &[], world);
+    }
+}
+
+/// Unloads a script from the runtime, along with its instances, the event handlers it registered
+/// and the state kept for it by the ops.
+fn unload_script(runtime: &JsRuntime, handle: &Handle<JsScript>, world: &mut World) {
+    instances::unload_script_instances(runtime, handle, world);
+    events::remove_event_handlers(runtime, handle, |_, _| true, world);
+    runtime.unload_script(handle);
+}
+
+/// Returns the result of the `saveState` function of a loaded script, or `None` if the script
+/// doesn't export one, it threw an error, or it didn't return anything.
+fn save_state(
+    runtime: &JsRuntime,
+    handle: &Handle<JsScript>,
+    world: &mut World,
+) -> Option<serde_json::Value> {
+    runtime
+        .call_script_fn(handle, "saveState", &[], world)
+        .unwrap_or_else(|error| {
+            error!("{error}");
+            world.send_event(error);
+            None
+        })
+        .filter(|state| !state.is_null())
+}
+
+/// Runs a lifecycle hook of a script, reporting the error if it throws one.
+fn run_hook(
+    runtime: &JsRuntime,
+    handle: &Handle<JsScript>,
+    hook: &str,
+    args: &[serde_json::Value],
+    world: &mut World,
+) {
+    if let Err(error) = runtime.run_script(handle, hook, args, world) {
+        error!("{error}");
+        world.send_event(error);
+    }
+}
+
+/// System to send [`JsScriptError`] events for the scripts that the [`JsScriptLoader`] failed to
+/// transpile.
+fn send_transpile_errors(
+    transpile_errors: Res<TranspileErrors>,
+    mut errors: EventWriter<JsScriptError>,
+) {
+    let mut transpile_errors = transpile_errors.0.lock().unwrap();
+    errors.send_batch(
+        transpile_errors
+            .drain(..)
+            .map(|(path, message)| JsScriptError {
+                handle: Handle::weak(AssetPath::from(path.as_path()).into()),
+                path,
+                stage_fn: None,
+                message,
+                stack: None,
+            }),
+    );
+}
+
+/// Returns weak handles to all of the scripts that import the given script, either directly or
+/// through other scripts.
+fn dependent_scripts(
+    assets: &Assets<JsScript>,
+    handle: &Handle<JsScript>,
+) -> Vec<Handle<JsScript>> {
+    let mut dependents: Vec<Handle<JsScript>> = Vec::new();
+
+    // Keep looking for scripts importing the script or one of its dependents until no new ones
+    // are found.
+    loop {
+        let new_dependents = assets
+            .iter()
+            .map(|(id, script)| (Handle::weak(id), script))
+            .filter(|(dependent, script)| {
+                dependent != handle
+                    && !dependents.contains(dependent)
+                    && script
+                        .dependency_handles()
+                        .any(|dependency| &dependency == handle || dependents.contains(&dependency))
+            })
+            .map(|(dependent, _)| dependent)
+            .collect::<Vec<_>>();
+
+        if new_dependents.is_empty() {
+            break;
+        }
+        dependents.extend(new_dependents);
+    }
+
+    dependents
 }
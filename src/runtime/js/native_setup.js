@@ -9,7 +9,30 @@
         try {
             return Deno.core.opSync("op_bevy_mod_js_scripting", OP_NAME_MAP[op_name], args);
         } catch (e) {
-            throw `Error during \`${op_name}\`: ${e}`
+            // Throw an error object so that the error has a stack trace
+            throw new Error(`Error during \`${op_name}\`: ${e}`);
         }
     }
+
+    // Exports of every loaded script module, keyed by the asset path of the script
+    const modules = new Map();
+
+    // Register the exports of a script module so that other scripts can import it
+    window.bevyModJsScriptingRegisterModule = (path, exports) => {
+        modules.set(path, exports);
+    }
+
+    // Remove the exports of an unloaded script module
+    window.bevyModJsScriptingUnregisterModule = (path) => {
+        modules.delete(path);
+    }
+
+    // Get the exports of a script module. Imports in scripts are transpiled to calls to this.
+    window.bevyModJsScriptingImport = (path) => {
+        const exports = modules.get(path);
+        if (!exports) {
+            throw `Imported module \`${path}\` has not been loaded`;
+        }
+        return exports;
+    }
 })(globalThis);
@@ -6,7 +6,30 @@ export function setup_js_globals(bevyModJsScripting, op_name_map_str) {
         try {
             return bevyModJsScripting.op_sync(op_name_map[op_name], args);
         } catch (e) {
-            throw `Error during \`${op_name}\`: ${e}`
+            // Throw an error object so that the error has a stack trace
+            throw new Error(`Error during \`${op_name}\`: ${e}`);
         }
     }
+
+    // Exports of every loaded script module, keyed by the asset path of the script
+    const modules = new Map();
+
+    // Register the exports of a script module so that other scripts can import it
+    window.bevyModJsScriptingRegisterModule = (path, exports) => {
+        modules.set(path, exports);
+    }
+
+    // Remove the exports of an unloaded script module
+    window.bevyModJsScriptingUnregisterModule = (path) => {
+        modules.delete(path);
+    }
+
+    // Get the exports of a script module. Imports in scripts are transpiled to calls to this.
+    window.bevyModJsScriptingImport = (path) => {
+        const exports = modules.get(path);
+        if (!exports) {
+            throw `Imported module \`${path}\` has not been loaded`;
+        }
+        return exports;
+    }
 }
@@ -1,10 +1,10 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use bevy::{prelude::*, utils::HashMap};
+use bevy::{ecs::change_detection::MAX_CHANGE_AGE, prelude::*, utils::HashMap};
 use bevy_reflect::TypeRegistry;
 use type_map::TypeMap;
 
-use crate::asset::JsScript;
+use crate::{asset::JsScript, transpile::module_key};
 
 #[cfg(target_arch = "wasm32")]
 mod wasm;
@@ -23,16 +23,122 @@ pub mod ops;
 /// Currently we have a native runtime built on [`deno_core`] and a web runtime utilizing
 /// [`wasm_bindgen`].
 pub trait JsRuntimeApi: FromWorld {
-    /// Load a script
+    /// Load a script from the [`Assets<JsScript>`] of the world
     ///
     /// This will not reload a script that has already been loaded unless `reload` is set to `true`.
-    fn load_script(&self, handle: &Handle<JsScript>, script: &JsScript, reload: bool);
+    /// The script module is evaluated with access to the world.
+    fn load_script(
+        &self,
+        handle: &Handle<JsScript>,
+        reload: bool,
+        world: &mut World,
+    ) -> Result<(), JsScriptError>;
+
+    /// Unload a script, removing it from the runtime
+    ///
+    /// Scripts that already imported the script keep the values they imported from it.
+    fn unload_script(&self, handle: &Handle<JsScript>);
 
     /// Returns whether or not a script has been loaded yet
     fn has_loaded(&self, handle: &Handle<JsScript>) -> bool;
 
-    /// Run a script
-    fn run_script(&self, handle: &Handle<JsScript>, fn_name: &str, world: &mut World);
+    /// Run a function exported by a script, passing it the given arguments
+    ///
+    /// Nothing is run if the script doesn't export the function. Returns an error if the script
+    /// function threw an exception.
+    ///
+    /// A function stored in an exported object can be run by naming it `object.key`.
+    fn run_script(
+        &self,
+        handle: &Handle<JsScript>,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<(), JsScriptError>;
+
+    /// Create an instance of a loaded script for an entity
+    ///
+    /// Functions run on the instance are called with `this` set to the instance, which inherits
+    /// from the default export of the script if it is an object, or from its exports otherwise,
+    /// and has an `entity` property. Reloading the script keeps the state of its instances.
+    fn create_instance(&self, handle: &Handle<JsScript>, entity: Entity);
+
+    /// Remove the instance of a script for an entity
+    fn remove_instance(&self, handle: &Handle<JsScript>, entity: Entity);
+
+    /// Run a function exported by a script on the instance of the script for an entity
+    ///
+    /// Nothing is run if the instance doesn't exist or the script doesn't export the function.
+    fn run_instance(
+        &self,
+        handle: &Handle<JsScript>,
+        entity: Entity,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<(), JsScriptError>;
+
+    /// Run a function exported by a script, passing it the given arguments, and return its
+    /// result
+    ///
+    /// Returns `None` if the script doesn't export the function, and an error if the script
+    /// function threw an exception or its result couldn't be converted to JSON.
+    fn call_script_fn(
+        &self,
+        handle: &Handle<JsScript>,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<Option<serde_json::Value>, JsScriptError>;
+
+    /// Call a function exported by a script with the given arguments, and return its result
+    ///
+    /// Unlike [`call_script_fn`][Self::call_script_fn], calling a function that the script doesn't
+    /// export, or calling a script that hasn't been loaded yet, is an error. The runtime is a
+    /// non-send resource, so it has to be removed from the world while calling:
+    ///
+    /// ```ignore
+    /// fn pick_action(world: &mut World) {
+    ///     let runtime = world.remove_non_send_resource::<JsRuntime>().unwrap();
+    ///     let action = runtime.call(&ai_script, "pickAction", &[json!({ "health": 10 })], world);
+    ///     world.insert_non_send_resource(runtime);
+    ///     // ...
+    /// }
+    /// ```
+    fn call(
+        &self,
+        handle: &Handle<JsScript>,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> anyhow::Result<serde_json::Value> {
+        if !self.has_loaded(handle) {
+            anyhow::bail!("Cannot call `{fn_name}` of a script that has not been loaded");
+        }
+
+        self.call_script_fn(handle, fn_name, args, world)?
+            .ok_or_else(|| anyhow::format_err!("Script does not export a `{fn_name}` function"))
+    }
+
+    /// Run a global function set up by the JavaScript of the runtime's ops, on behalf of a script
+    ///
+    /// The ops called by the function see the info of the given script, along with the entity of
+    /// its instance if the function is run on behalf of an instance, and errors are reported for
+    /// that script.
+    fn run_global_fn(
+        &self,
+        handle: &Handle<JsScript>,
+        entity: Option<Entity>,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<(), JsScriptError>;
+
+    /// Returns a JSON snapshot of the values exported by a script
+    ///
+    /// Exported functions are left out, and [`serde_json::Value::Null`] is returned if the script
+    /// isn't loaded or its exports can't be serialized.
+    fn snapshot_exports(&self, handle: &Handle<JsScript>) -> serde_json::Value;
 
     /// Run during an exclusive system executed before [`CoreStage::First`] to allow the engine to
     /// do any pre-frame preparation.
@@ -47,6 +153,191 @@ pub trait JsRuntimeApi: FromWorld {
     }
 }
 
+/// The object that the functions run by a runtime are looked up on.
+#[derive(Clone, Copy)]
+enum FnReceiver {
+    /// The exports of the script's module
+    Exports,
+    /// The instance of the script for an entity
+    Instance(Entity),
+    /// The global object, with the function run on behalf of the script, or of its instance for
+    /// an entity
+    Global(Option<Entity>),
+}
+
+impl FnReceiver {
+    /// Returns the entity of the instance that the function is run on, if any
+    fn entity(&self) -> Option<Entity> {
+        match self {
+            FnReceiver::Instance(entity) => Some(*entity),
+            FnReceiver::Global(entity) => *entity,
+            FnReceiver::Exports => None,
+        }
+    }
+}
+
+/// The code that [`module_wrapper_code`] wraps the code of a script in, which evaluates the module,
+/// registers its exports with the module registry, and returns the module's exports object.
+const MODULE_WRAPPER: &str = r#"((window) => {
+    "use strict";
+    const exports = {};
+    ((exports) => {
+{code}
+    })(exports);
+    bevyModJsScriptingRegisterModule({module}, exports);
+    return exports;
+})(globalThis)"#;
+
+/// Returns the number of lines that [`module_wrapper_code`] inserts before the code of the script.
+fn module_wrapper_line_offset() -> u32 {
+    let (before_code, _) = MODULE_WRAPPER.split_once("{code}").unwrap();
+    before_code.matches('\n').count() as u32
+}
+
+/// Wraps the transpiled code of a script in an expression that evaluates the module, registers
+/// its exports with the module registry, and returns the module's exports object.
+fn module_wrapper_code(script: &JsScript) -> String {
+    let module = serde_json::to_string(&module_key(&script.path)).unwrap();
+    let (before_code, after_code) = MODULE_WRAPPER.split_once("{code}").unwrap();
+    format!(
+        "{before_code}{}{}",
+        script.source,
+        after_code.replace("{module}", &module)
+    )
+}
+
+/// Returns the code that removes an unloaded script from the module registry.
+fn unregister_module_code(path: &Path) -> String {
+    let module = serde_json::to_string(&module_key(path)).unwrap();
+    format!("bevyModJsScriptingUnregisterModule({module})")
+}
+
+/// The source maps of the loaded scripts, used to map the locations in stack traces back to the
+/// original script sources.
+struct ScriptSourceMaps {
+    /// Source maps keyed by the name the script was evaluated under
+    source_maps: HashMap<String, sourcemap::SourceMap>,
+    /// The number of lines preceding the transpiled code of a script when it is evaluated
+    line_offset: u32,
+}
+
+impl ScriptSourceMaps {
+    fn new(line_offset: u32) -> Self {
+        Self {
+            source_maps: default(),
+            line_offset,
+        }
+    }
+
+    /// Adds the source map of a script, returning the source map of its previous version.
+    fn insert(&mut self, script: &JsScript) -> Option<sourcemap::SourceMap> {
+        self.source_maps
+            .insert(module_key(&script.path), script.source_map.clone())
+    }
+
+    /// Puts back the source map of the previous version of a script, after the new version failed
+    /// to load.
+    fn restore(&mut self, path: &Path, previous: Option<sourcemap::SourceMap>) {
+        match previous {
+            Some(source_map) => {
+                self.source_maps.insert(module_key(path), source_map);
+            }
+            None => self.remove(path),
+        }
+    }
+
+    /// Removes the source map of an unloaded script.
+    fn remove(&mut self, path: &Path) {
+        self.source_maps.remove(&module_key(path));
+    }
+
+    /// Maps a 1-based line and column in an evaluated script to the corresponding location in the
+    /// original source of the script.
+    fn map_location(
+        &self,
+        script_name: &str,
+        line: u32,
+        column: u32,
+    ) -> Option<(String, u32, u32)> {
+        let source_map = self.source_maps.get(script_name)?;
+        let line = line.checked_sub(self.line_offset + 1)?;
+        let token = source_map.lookup_token(line, column.saturating_sub(1))?;
+
+        Some((
+            token.get_source().unwrap_or(script_name).to_owned(),
+            token.get_src_line() + 1,
+            token.get_src_col() + 1,
+        ))
+    }
+
+    /// Formats a stack frame, mapping its location to the original script source.
+    fn format_frame(
+        &self,
+        function_name: Option<&str>,
+        script_name: Option<&str>,
+        line: u32,
+        column: u32,
+    ) -> String {
+        let (script_name, line, column) = script_name
+            .and_then(|name| self.map_location(name, line, column))
+            .unwrap_or_else(|| {
+                let script_name = script_name.unwrap_or("<unknown>").to_owned();
+                (script_name, line, column)
+            });
+
+        format!(
+            "    at {} ({}:{}:{})",
+            function_name.unwrap_or("<unknown>"),
+            script_name,
+            line,
+            column
+        )
+    }
+
+    /// Maps all of the `script_name:line:column` locations in a JavaScript stack trace string to
+    /// the original script sources.
+    fn map_stack(&self, stack: &str) -> String {
+        stack
+            .lines()
+            .map(|line| self.map_stack_line(line).unwrap_or_else(|| line.to_owned()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn map_stack_line(&self, stack_line: &str) -> Option<String> {
+        self.source_maps.keys().find_map(|script_name| {
+            // The script name has to start the location, so that `main.ts` doesn't match the
+            // location of `scripts/main.ts`
+            let start = stack_line
+                .match_indices(&format!("{script_name}:"))
+                .map(|(start, _)| start)
+                .find(|&start| {
+                    stack_line[..start]
+                        .chars()
+                        .next_back()
+                        .map_or(true, |c| c == '(' || c == '@' || c.is_whitespace())
+                })?;
+            let location = &stack_line[start + script_name.len() + 1..];
+
+            // Parse the `line:column` following the script name
+            let line_len = location.find(|c: char| !c.is_ascii_digit())?;
+            let line = location[..line_len].parse().ok()?;
+            let location = location[line_len..].strip_prefix(':')?;
+            let column_len = location
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(location.len());
+            let column = location[..column_len].parse().ok()?;
+            let rest = &location[column_len..];
+
+            let (source, line, column) = self.map_location(script_name, line, column)?;
+            Some(format!(
+                "{}{source}:{line}:{column}{rest}",
+                &stack_line[..start]
+            ))
+        })
+    }
+}
+
 // Hash map of op names to op implementation
 pub type OpMap = HashMap<&'static str, Box<dyn JsRuntimeOp>>;
 
@@ -121,10 +412,122 @@ pub struct JsRuntimeConfig {
     pub custom_ops: OpMap,
 }
 
+/// Event sent when a script fails to transpile, fails to load, or throws an exception while
+/// running one of its functions.
+#[derive(Debug, Clone)]
+pub struct JsScriptError {
+    /// The script that failed
+    pub handle: Handle<JsScript>,
+    /// The asset path of the script
+    pub path: PathBuf,
+    /// The name of the script function that was running, or `None` if the script failed to
+    /// transpile or load.
+    pub stage_fn: Option<String>,
+    /// The error message
+    pub message: String,
+    /// The stack trace of the error, with locations mapped to the original script sources
+    pub stack: Option<String>,
+}
+
+impl JsScriptError {
+    /// Create an error from an error message that may be followed by a stack trace on the
+    /// following lines.
+    fn from_message_with_stack(
+        handle: &Handle<JsScript>,
+        path: &Path,
+        stage_fn: Option<&str>,
+        message: &str,
+    ) -> Self {
+        let (message, stack) = match message.split_once('\n') {
+            Some((message, stack)) => (message, Some(stack.to_owned())),
+            None => (message, None),
+        };
+
+        Self {
+            handle: handle.clone_weak(),
+            path: path.to_owned(),
+            stage_fn: stage_fn.map(ToOwned::to_owned),
+            message: message.to_owned(),
+            stack,
+        }
+    }
+}
+
+impl std::fmt::Display for JsScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.stage_fn {
+            Some(stage_fn) => write!(f, "Error running `{stage_fn}` of script {:?}: ", self.path)?,
+            None => write!(f, "Error loading script {:?}: ", self.path)?,
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(stack) = &self.stack {
+            write!(f, "\n{stack}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for JsScriptError {}
+
 /// Info about the currently executing script, exposed to [`JsRuntimeOp`]s.
 pub struct ScriptInfo {
     pub path: PathBuf,
     pub handle: Handle<JsScript>,
+    /// The entity of the script instance that the function is run on, if any
+    pub entity: Option<Entity>,
+    /// The change tick of the end of the previous call of the script, or of its instance, which
+    /// the `changed` and `added` query filters are relative to
+    pub last_run_tick: u32,
+}
+
+/// The change ticks of the last calls of the scripts and their instances
+#[derive(Default)]
+struct ScriptRunTicks(HashMap<(Handle<JsScript>, Option<Entity>), u32>);
+
+impl ScriptRunTicks {
+    /// Returns the change tick of the end of the previous call of a script, or a tick old enough
+    /// for every change to be newer if the script hasn't been called yet
+    fn last_run_tick(
+        &self,
+        handle: &Handle<JsScript>,
+        entity: Option<Entity>,
+        world: &World,
+    ) -> u32 {
+        self.0
+            .get(&(handle.clone_weak(), entity))
+            .copied()
+            .unwrap_or_else(|| world.read_change_tick().wrapping_sub(MAX_CHANGE_AGE))
+    }
+
+    /// Records the end of a call of a script
+    fn end_call(&mut self, handle: &Handle<JsScript>, entity: Option<Entity>, world: &World) {
+        // Like after a system runs, the tick is advanced so that the changes made after the call
+        // are newer than it.
+        let tick = world.increment_change_tick();
+        self.0.insert((handle.clone_weak(), entity), tick);
+    }
+
+    /// Clamps the ticks of the calls that are older than [`MAX_CHANGE_AGE`], like the last change
+    /// ticks of the systems, so that they don't wrap around and appear to be newer than the
+    /// changes made since
+    fn check_change_ticks(&mut self, world: &World) {
+        let change_tick = world.read_change_tick();
+        for last_run_tick in self.0.values_mut() {
+            if change_tick.wrapping_sub(*last_run_tick) > MAX_CHANGE_AGE {
+                *last_run_tick = change_tick.wrapping_sub(MAX_CHANGE_AGE);
+            }
+        }
+    }
+
+    /// Forgets the calls of a script and its instances
+    fn remove_script(&mut self, handle: &Handle<JsScript>) {
+        self.0.retain(|(script, _), _| script != handle);
+    }
+
+    /// Forgets the calls of a script instance
+    fn remove_instance(&mut self, handle: &Handle<JsScript>, entity: Entity) {
+        self.0.remove(&(handle.clone_weak(), Some(entity)));
+    }
 }
 
 pub struct OpContext<'a> {
@@ -163,6 +566,34 @@ pub trait JsRuntimeOp {
         // Fix clippy warning by using variables
         let _ = (op_state, world);
     }
+
+    /// Function called when a script is unloaded, to allow the op to drop the state it keeps for
+    /// the script
+    fn script_unloaded(&self, op_state: &mut TypeMap, script: &Handle<JsScript>) {
+        // Fix clippy warning by using variables
+        let _ = (op_state, script);
+    }
+
+    /// Function called before a new version of a loaded script is evaluated
+    fn script_reloading(&self, op_state: &mut TypeMap, script: &Handle<JsScript>) {
+        // Fix clippy warning by using variables
+        let _ = (op_state, script);
+    }
+
+    /// Function called after a new version of a loaded script has been evaluated, to allow the op
+    /// to drop the state it keeps for the previous version of the script module if it `loaded`,
+    /// or to keep it if the previous version keeps running
+    fn script_reloaded(&self, op_state: &mut TypeMap, script: &Handle<JsScript>, loaded: bool) {
+        // Fix clippy warning by using variables
+        let _ = (op_state, script, loaded);
+    }
+
+    /// Function called when the instance of a script for an entity is removed, to allow the op to
+    /// drop the state it keeps for the instance
+    fn instance_removed(&self, op_state: &mut TypeMap, script: &Handle<JsScript>, entity: Entity) {
+        // Fix clippy warning by using variables
+        let _ = (op_state, script, entity);
+    }
 }
 
 impl<T: Fn(OpContext<'_>, &mut World, serde_json::Value) -> anyhow::Result<serde_json::Value>>
@@ -177,3 +608,83 @@ impl<T: Fn(OpContext<'_>, &mut World, serde_json::Value) -> anyhow::Result<serde
         self(context, world, args)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transpile::transpile;
+
+    fn transpiled_script(path: &str, source: &str) -> JsScript {
+        let transpiled = transpile(Path::new(path), source).unwrap();
+        JsScript {
+            source: transpiled.code,
+            path: path.into(),
+            dependencies: transpiled.dependencies,
+            source_map: transpiled.source_map,
+        }
+    }
+
+    /// Returns the 1-based line and column of some code in the wrapped code of a script.
+    fn wrapped_location(script: &JsScript, code: &str) -> (u32, u32) {
+        module_wrapper_code(script)
+            .lines()
+            .enumerate()
+            .find_map(|(line, text)| Some((line as u32 + 1, text.find(code)? as u32 + 1)))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_module_wrapper_line_offset() {
+        let script = transpiled_script("scripts/test.ts", "first();\n");
+        let (line, _) = wrapped_location(&script, "first();");
+
+        assert_eq!(line, module_wrapper_line_offset() + 1);
+    }
+
+    #[test]
+    fn test_map_stack_to_original_source() {
+        let script = transpiled_script(
+            "scripts/test.ts",
+            "type Damage = number;\n\
+             \n\
+             interface Options {\n    scale: number;\n}\n\
+             \n\
+             export function fail(options: Options): Damage {\n    throw new Error(\"oops\");\n}\n",
+        );
+        let mut source_maps = ScriptSourceMaps::new(module_wrapper_line_offset());
+        source_maps.insert(&script);
+
+        let (line, column) = wrapped_location(&script, "throw");
+        assert_eq!(
+            source_maps.map_location("scripts/test.ts", line, column),
+            Some(("scripts/test.ts".to_owned(), 8, 5))
+        );
+
+        let stack = format!("Error: oops\n    at fail (scripts/test.ts:{line}:{column})");
+        assert_eq!(
+            source_maps.map_stack(&stack),
+            "Error: oops\n    at fail (scripts/test.ts:8:5)"
+        );
+    }
+
+    #[test]
+    fn test_map_stack_of_scripts_with_the_same_file_name() {
+        let nested = transpiled_script(
+            "scripts/main.ts",
+            "type Damage = number;\n\
+             \n\
+             export function fail(): Damage {\n    throw new Error(\"oops\");\n}\n",
+        );
+        let root = transpiled_script("main.ts", "export function other() {}\n");
+        let mut source_maps = ScriptSourceMaps::new(module_wrapper_line_offset());
+        source_maps.insert(&root);
+        source_maps.insert(&nested);
+
+        let (line, column) = wrapped_location(&nested, "throw");
+        let stack = format!("Error: oops\n    at fail (scripts/main.ts:{line}:{column})");
+        assert_eq!(
+            source_maps.map_stack(&stack),
+            "Error: oops\n    at fail (scripts/main.ts:4:5)"
+        );
+    }
+}
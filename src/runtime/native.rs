@@ -2,14 +2,19 @@ use std::{cell::RefCell, path::PathBuf};
 
 use bevy::{prelude::*, utils::HashMap};
 use deno_core::{
-    error::AnyError, v8, Extension, JsRuntime as DenoJsRuntime, OpState, ResourceId, RuntimeOptions,
+    error::AnyError, serde_v8, v8, Extension, JsRuntime as DenoJsRuntime, OpState, ResourceId,
+    RuntimeOptions,
 };
 use type_map::TypeMap;
 
 use super::JsRuntimeApi;
 use crate::{
     asset::JsScript,
-    runtime::{JsRuntimeConfig, OpContext, OpNames, Ops, ScriptInfo},
+    runtime::{
+        FnReceiver, JsRuntimeConfig, JsRuntimeOp, JsScriptError, OpContext, OpNames, Ops,
+        ScriptInfo, ScriptRunTicks, ScriptSourceMaps,
+    },
+    transpile::module_key,
 };
 
 /// Resource stored in the Deno runtime to give access to the Bevy world
@@ -27,10 +32,15 @@ pub struct JsRuntime(RefCell<JsRuntimeInner>);
 
 pub struct JsRuntimeInner {
     scripts: HashMap<Handle<JsScript>, LoadedScriptData>,
+    /// The instances of the scripts attached to entities
+    instances: HashMap<(Handle<JsScript>, Entity), v8::Global<v8::Value>>,
     runtime: deno_core::JsRuntime,
+    source_maps: ScriptSourceMaps,
+    run_ticks: ScriptRunTicks,
 }
 
 struct LoadedScriptData {
+    /// The exports object of the script's module
     output: v8::Global<v8::Value>,
     path: PathBuf,
 }
@@ -84,65 +94,131 @@ impl FromWorld for JsRuntime {
 
         Self(RefCell::new(JsRuntimeInner {
             scripts: Default::default(),
+            instances: Default::default(),
             runtime,
+            source_maps: ScriptSourceMaps::new(super::module_wrapper_line_offset()),
+            run_ticks: default(),
         }))
     }
 }
 
 impl JsRuntimeApi for JsRuntime {
-    fn load_script(&self, handle: &Handle<JsScript>, script: &JsScript, reload: bool) {
+    fn load_script(
+        &self,
+        handle: &Handle<JsScript>,
+        reload: bool,
+        world: &mut World,
+    ) -> Result<(), JsScriptError> {
         let mut this = self.borrow_mut();
         let already_loaded = this.scripts.contains_key(handle);
 
         // Skip if already loaded and we aren't intentionally reloading
         if already_loaded && !reload {
-            return;
+            return Ok(());
         }
 
-        // Helper to load script
-        let mut load_script = || {
-            // Get the script source code
-            let code = &script.source;
-
-            // Wrap the script in a closure
-            let code = format!(
-                r#"
-                    "strict_mode";
-                    
-                    ((window) => {{
-                        {code}
-                    }})(globalThis)
-                "#,
-            );
-
-            // Make script info available to the runtime
-            this.runtime.op_state().borrow_mut().put(ScriptInfo {
-                path: script.path.clone(),
-                handle: handle.clone_weak(),
-            });
+        let script = world
+            .resource::<Assets<JsScript>>()
+            .get(handle)
+            .expect("Script asset should be loaded");
+        let path = script.path.clone();
 
-            // Run the script and get it's output
-            let output = this
-                .runtime
-                .execute_script(&script.path.to_string_lossy(), &code)?;
+        // Wrap the script in a closure that evaluates to the module's exports
+        let code = super::module_wrapper_code(script);
 
-            debug!(?script.path, "Loaded script");
+        // Register the source map so that errors can be mapped to the original source
+        let previous_source_map = this.source_maps.insert(script);
 
-            // Store the module's exported namespace in the script map
-            this.scripts.insert(
-                handle.clone_weak(),
-                LoadedScriptData {
-                    output,
-                    path: script.path.clone(),
-                },
-            );
+        if already_loaded {
+            with_op_states(&mut this.runtime, |op, op_state| {
+                op.script_reloading(op_state, handle)
+            });
+        }
+
+        // Make script info available to the runtime
+        this.runtime.op_state().borrow_mut().put(ScriptInfo {
+            path: path.clone(),
+            handle: handle.clone_weak(),
+            entity: None,
+            last_run_tick: 0,
+        });
+
+        // Run the script and get it's output, with access to the world
+        let output = with_world(world, &mut this.runtime, |runtime| {
+            runtime.execute_script(&module_key(&path), &code)
+        });
 
-            Ok::<_, anyhow::Error>(())
+        // Report errors. The script data is only replaced once the new version of the script has
+        // been evaluated successfully, so a failed reload keeps the previous version running.
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                if already_loaded {
+                    with_op_states(&mut this.runtime, |op, op_state| {
+                        op.script_reloaded(op_state, handle, false)
+                    });
+                }
+                let message = this.source_maps.map_stack(&e.to_string());
+                this.source_maps.restore(&path, previous_source_map);
+                return Err(JsScriptError::from_message_with_stack(
+                    handle, &path, None, &message,
+                ));
+            }
         };
 
-        // Load script or report errors
-        if let Err(e) = load_script() {
-            error!("Error running script: {}", e);
+        debug!(?path, "Loaded script");
+
+        // Make the instances of the script use the new version of the script
+        {
+            let JsRuntimeInner {
+                runtime, instances, ..
+            } = &mut *this;
+            let scope = &mut runtime.handle_scope();
+            let exports = v8::Local::new(scope, &output);
+            for ((instance_handle, _), instance) in instances.iter() {
+                if instance_handle == handle {
+                    let instance = v8::Local::new(scope, instance);
+                    call_global_fn(
+                        scope,
+                        "bevyModJsScriptingUpdateInstance",
+                        &[instance, exports],
+                    );
+                }
+            }
+        }
+
+        if already_loaded {
+            with_op_states(&mut this.runtime, |op, op_state| {
+                op.script_reloaded(op_state, handle, true)
+            });
+        }
+
+        // Store the module's exports in the script map
+        this.scripts
+            .insert(handle.clone_weak(), LoadedScriptData { output, path });
+
+        Ok(())
+    }
+
+    fn unload_script(&self, handle: &Handle<JsScript>) {
+        let mut this = self.borrow_mut();
+
+        if let Some(script) = this.scripts.remove(handle) {
+            this.source_maps.remove(&script.path);
+            this.instances
+                .retain(|(instance_handle, _), _| instance_handle != handle);
+            this.run_ticks.remove_script(handle);
+
+            let code = super::unregister_module_code(&script.path);
+            if let Err(e) = this.runtime.execute_script("bevy_mod_js_scripting", &code) {
+                error!(?script.path, "Error unregistering script module: {e}");
+            }
+
+            with_op_states(&mut this.runtime, |op, op_state| {
+                op.script_unloaded(op_state, handle)
+            });
+
+            debug!(?script.path, "Unloaded script");
         }
     }
 
@@ -150,24 +226,224 @@ impl JsRuntimeApi for JsRuntime {
         self.borrow().scripts.contains_key(handle)
     }
 
-    fn run_script(&self, handle: &Handle<JsScript>, fn_name_str: &str, world: &mut World) {
+    fn run_script(
+        &self,
+        handle: &Handle<JsScript>,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<(), JsScriptError> {
+        self.call_fn(handle, FnReceiver::Exports, fn_name, args, world, |_, _| {
+            Ok(())
+        })
+        .map(drop)
+    }
+
+    fn create_instance(&self, handle: &Handle<JsScript>, entity: Entity) {
         let mut this = self.borrow_mut();
-        let JsRuntimeInner { scripts, runtime } = &mut *this;
+        let JsRuntimeInner {
+            scripts,
+            instances,
+            runtime,
+            ..
+        } = &mut *this;
 
-        // Get the script output
         let script = if let Some(script) = scripts.get(handle) {
             script
         } else {
             return;
         };
 
+        let scope = &mut runtime.handle_scope();
+        let exports = v8::Local::new(scope, &script.output);
+        let id = v8::Integer::new_from_unsigned(scope, entity.index()).into();
+        let generation = v8::Integer::new_from_unsigned(scope, entity.generation()).into();
+
+        match call_global_fn(
+            scope,
+            "bevyModJsScriptingCreateInstance",
+            &[exports, id, generation],
+        ) {
+            Some(instance) => {
+                let instance = v8::Global::new(scope, instance);
+                instances.insert((handle.clone_weak(), entity), instance);
+            }
+            None => error!(?script.path, ?entity, "Could not create script instance"),
+        }
+    }
+
+    fn remove_instance(&self, handle: &Handle<JsScript>, entity: Entity) {
+        let mut this = self.borrow_mut();
+        this.instances.remove(&(handle.clone_weak(), entity));
+        this.run_ticks.remove_instance(handle, entity);
+        with_op_states(&mut this.runtime, |op, op_state| {
+            op.instance_removed(op_state, handle, entity)
+        });
+    }
+
+    fn run_instance(
+        &self,
+        handle: &Handle<JsScript>,
+        entity: Entity,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<(), JsScriptError> {
+        self.call_fn(
+            handle,
+            FnReceiver::Instance(entity),
+            fn_name,
+            args,
+            world,
+            |_, _| Ok(()),
+        )
+        .map(drop)
+    }
+
+    fn call_script_fn(
+        &self,
+        handle: &Handle<JsScript>,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<Option<serde_json::Value>, JsScriptError> {
+        self.call_fn(
+            handle,
+            FnReceiver::Exports,
+            fn_name,
+            args,
+            world,
+            |scope, value| {
+                serde_v8::from_v8(scope, value)
+                    .map_err(|e| format!("Could not convert return value: {e}"))
+            },
+        )
+    }
+
+    fn run_global_fn(
+        &self,
+        handle: &Handle<JsScript>,
+        entity: Option<Entity>,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<(), JsScriptError> {
+        self.call_fn(
+            handle,
+            FnReceiver::Global(entity),
+            fn_name,
+            args,
+            world,
+            |_, _| Ok(()),
+        )
+        .map(drop)
+    }
+
+    fn snapshot_exports(&self, handle: &Handle<JsScript>) -> serde_json::Value {
+        let mut this = self.borrow_mut();
+        let JsRuntimeInner {
+            scripts, runtime, ..
+        } = &mut *this;
+
+        let script = if let Some(script) = scripts.get(handle) {
+            script
+        } else {
+            return serde_json::Value::Null;
+        };
+
+        let scope = &mut runtime.handle_scope();
+        let tc_scope = &mut v8::TryCatch::new(scope);
+        let output = v8::Local::new(tc_scope, &script.output);
+
+        // Serialize the exports with `JSON.stringify()`, which skips the functions
+        v8::json::stringify(tc_scope, output)
+            .and_then(|json| serde_json::from_str(&json.to_rust_string_lossy(tc_scope)).ok())
+            .unwrap_or_default()
+    }
+
+    fn frame_start(&self, world: &mut World) {
+        let this: &mut JsRuntimeInner = &mut self.borrow_mut();
+        let op_state = this.runtime.op_state();
+        let mut op_state = op_state.borrow_mut();
+
+        with_state(&mut op_state, |op_state, ops: &mut Ops| {
+            with_state(op_state, |_, script_op_state: &mut TypeMap| {
+                for op in ops {
+                    op.frame_start(script_op_state, world);
+                }
+            });
+        });
+    }
+
+    fn frame_end(&self, world: &mut World) {
+        let this: &mut JsRuntimeInner = &mut self.borrow_mut();
+
+        {
+            let op_state = this.runtime.op_state();
+            let mut op_state = op_state.borrow_mut();
+
+            with_state(&mut op_state, |op_state, ops: &mut Ops| {
+                with_state(op_state, |_, script_op_state: &mut TypeMap| {
+                    for op in ops {
+                        op.frame_end(script_op_state, world);
+                    }
+                });
+            });
+        }
+
+        this.run_ticks.check_change_ticks(world);
+    }
+}
+
+impl JsRuntime {
+    /// Call a function exported by a script, converting its return value with `convert`
+    ///
+    /// If `entity` is set, the function is called on the instance of the script for that entity.
+    /// Returns `None` if the script or the instance isn't loaded, or if the script doesn't export
+    /// the function.
+    fn call_fn<T>(
+        &self,
+        handle: &Handle<JsScript>,
+        fn_receiver: FnReceiver,
+        fn_name_str: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+        convert: impl FnOnce(&mut v8::HandleScope, v8::Local<v8::Value>) -> Result<T, String>,
+    ) -> Result<Option<T>, JsScriptError> {
+        let mut this = self.borrow_mut();
+        let JsRuntimeInner {
+            scripts,
+            instances,
+            runtime,
+            source_maps,
+            run_ticks,
+        } = &mut *this;
+
+        // Get the script output
+        let script = if let Some(script) = scripts.get(handle) {
+            script
+        } else {
+            return Ok(None);
+        };
+
+        // Get the instance of the script for the entity
+        let instance = match fn_receiver {
+            FnReceiver::Instance(entity) => match instances.get(&(handle.clone_weak(), entity)) {
+                Some(instance) => Some(instance),
+                None => return Ok(None),
+            },
+            FnReceiver::Exports | FnReceiver::Global(_) => None,
+        };
+
         // Make script info available to the runtime
         runtime.op_state().borrow_mut().put(ScriptInfo {
             path: script.path.clone(),
             handle: handle.clone_weak(),
+            entity: fn_receiver.entity(),
+            last_run_tick: run_ticks.last_run_tick(handle, fn_receiver.entity(), world),
         });
 
-        with_world(world, runtime, |runtime| {
+        let result = with_world(world, runtime, |runtime| {
             let scope = &mut runtime.handle_scope();
             let output = v8::Local::new(scope, &script.output);
 
@@ -175,39 +451,79 @@ impl JsRuntimeApi for JsRuntime {
             let output = if let Ok(value) = v8::Local::<v8::Object>::try_from(output) {
                 value
             } else {
-                warn!(?script.path, "Script init() did not return an object. Skipping.");
-                return;
+                warn!(?script.path, "Script module did not evaluate to an object. Skipping.");
+                return Ok(None);
+            };
+
+            // Functions stored in an exported object are named `object.key`
+            let (export_name, key) = match fn_name_str.split_once('.') {
+                Some((export_name, key)) => (export_name, Some(key)),
+                None => (fn_name_str, None),
             };
 
             // Get a javascript value for the name of the function to call
             let fn_name = v8::String::new_from_utf8(
                 scope,
-                fn_name_str.as_bytes(),
+                export_name.as_bytes(),
                 v8::NewStringType::Internalized,
             )
             .unwrap();
 
-            // Get get the named function from the object
-            let script_fn = if let Some(script_fn) = output.get(scope, fn_name.into()) {
-                script_fn
-            } else {
-                warn!(?script.path, "Getting function named `{}` on script default export failed. Skipping.", fn_name_str);
-                return;
+            // Get the default export of the module, if it is an object
+            let default_key = v8::String::new(scope, "default").unwrap();
+            let default_export = output
+                .get(scope, default_key.into())
+                .and_then(|value| v8::Local::<v8::Object>::try_from(value).ok());
+
+            // Look for the function in the named exports of the module first, and fall back to
+            // the default export object, which may also hold the stage functions. Global functions
+            // are looked up on the global object instead.
+            let receivers = match fn_receiver {
+                FnReceiver::Global(_) => [Some(scope.get_current_context().global(scope)), None],
+                FnReceiver::Exports | FnReceiver::Instance(_) => [Some(output), default_export],
             };
+            let script_fn = receivers.into_iter().flatten().find_map(|mut receiver| {
+                let mut script_fn = receiver.get(scope, fn_name.into())?;
+                if let Some(key) = key {
+                    receiver = v8::Local::<v8::Object>::try_from(script_fn).ok()?;
+                    let key = v8::String::new(scope, key)?;
+                    script_fn = receiver.get(scope, key.into())?;
+                }
+                let script_fn = v8::Local::<v8::Function>::try_from(script_fn).ok()?;
+                Some((receiver, script_fn))
+            });
 
-            // Make sure the value is a function
-            let script_fn = if let Ok(value) = v8::Local::<v8::Function>::try_from(script_fn) {
-                value
+            let (receiver, script_fn) = if let Some(script_fn) = script_fn {
+                script_fn
             } else {
                 // It is valid to not have a function for a script stage so we don't print a warning if
                 // the function isn't found.
-                return;
+                return Ok(None);
+            };
+
+            // Convert the arguments to JavaScript values
+            let args = args
+                .iter()
+                .map(|arg| serde_v8::to_v8(scope, arg))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| JsScriptError {
+                    handle: handle.clone_weak(),
+                    path: script.path.clone(),
+                    stage_fn: Some(fn_name_str.to_owned()),
+                    message: format!("Could not convert arguments: {e}"),
+                    stack: None,
+                })?;
+
+            // Functions run on an instance are called with `this` set to the instance
+            let receiver = match instance {
+                Some(instance) => v8::Local::new(scope, instance),
+                None => receiver.into(),
             };
 
             let tc_scope = &mut v8::TryCatch::new(scope);
-            script_fn.call(tc_scope, output.into(), &[]);
+            let value = script_fn.call(tc_scope, receiver, &args);
             if let Some(message) = tc_scope.message() {
-                let mut stack_trace_message = String::new();
+                let mut frames = Vec::new();
                 let stack_trace = message.get_stack_trace(tc_scope).unwrap();
                 for i in 0..stack_trace.get_frame_count() {
                     let Some(frame) = stack_trace.get_frame(tc_scope, i) else { continue };
@@ -217,55 +533,63 @@ impl JsRuntimeApi for JsRuntime {
                     let script_name = frame
                         .get_script_name(tc_scope)
                         .map(|name| name.to_rust_string_lossy(tc_scope));
-                    stack_trace_message.push_str(&format!(
-                        "\n    at {} ({}:{}:{})",
-                        function_name.as_deref().unwrap_or("<unknown>"),
-                        script_name.as_deref().unwrap_or("<unknown>"),
-                        frame.get_line_number(),
-                        frame.get_column()
+                    frames.push(source_maps.format_frame(
+                        function_name.as_deref(),
+                        script_name.as_deref(),
+                        frame.get_line_number() as u32,
+                        frame.get_column() as u32,
                     ));
                 }
 
                 let message = message.get(tc_scope).to_rust_string_lossy(tc_scope);
                 let message = message.trim_end_matches("Uncought ");
 
-                error!("{message}{stack_trace_message}");
+                return Err(JsScriptError {
+                    handle: handle.clone_weak(),
+                    path: script.path.clone(),
+                    stage_fn: Some(fn_name_str.to_owned()),
+                    message: message.to_owned(),
+                    stack: (!frames.is_empty()).then(|| frames.join("\n")),
+                });
             }
-        });
-    }
-
-    fn frame_start(&self, world: &mut World) {
-        let this: &mut JsRuntimeInner = &mut self.borrow_mut();
-        let op_state = this.runtime.op_state();
-        let mut op_state = op_state.borrow_mut();
 
-        with_state(&mut op_state, |op_state, ops: &mut Ops| {
-            with_state(op_state, |_, script_op_state: &mut TypeMap| {
-                for op in ops {
-                    op.frame_start(script_op_state, world);
-                }
-            });
+            let value = value.unwrap_or_else(|| v8::undefined(tc_scope).into());
+            convert(tc_scope, value)
+                .map(Some)
+                .map_err(|message| JsScriptError {
+                    handle: handle.clone_weak(),
+                    path: script.path.clone(),
+                    stage_fn: Some(fn_name_str.to_owned()),
+                    message,
+                    stack: None,
+                })
         });
-    }
-
-    fn frame_end(&self, world: &mut World) {
-        let this: &mut JsRuntimeInner = &mut self.borrow_mut();
-
-        {
-            let op_state = this.runtime.op_state();
-            let mut op_state = op_state.borrow_mut();
 
-            with_state(&mut op_state, |op_state, ops: &mut Ops| {
-                with_state(op_state, |_, script_op_state: &mut TypeMap| {
-                    for op in ops {
-                        op.frame_end(script_op_state, world);
-                    }
-                });
-            });
+        // Only the calls of functions that the script exports count as runs of the script, and
+        // not the global functions run on its behalf
+        if !matches!(result, Ok(None)) && !matches!(fn_receiver, FnReceiver::Global(_)) {
+            run_ticks.end_call(handle, fn_receiver.entity(), world);
         }
+
+        result
     }
 }
 
+/// Calls one of the global functions set up by the runtime's JavaScript, returning `None` if the
+/// function doesn't exist or throws.
+fn call_global_fn<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    name: &str,
+    args: &[v8::Local<v8::Value>],
+) -> Option<v8::Local<'s, v8::Value>> {
+    let global = scope.get_current_context().global(scope);
+    let name = v8::String::new(scope, name)?;
+    let function = global.get(scope, name.into())?;
+    let function = v8::Local::<v8::Function>::try_from(function).ok()?;
+    let undefined = v8::undefined(scope).into();
+    function.call(scope, undefined, args)
+}
+
 /// Helper to insert the Bevy world into into the deno resource map while a closure is executed, and
 /// remove the world when the closure finishes.
 pub fn with_world<T>(
@@ -345,6 +669,22 @@ fn with_state<T: 'static, R, F: FnOnce(&mut OpState, &mut T) -> R>(state: &mut O
     r
 }
 
+/// Calls a function with each op and the op state of the scripts
+fn with_op_states(
+    runtime: &mut deno_core::JsRuntime,
+    mut f: impl FnMut(&dyn JsRuntimeOp, &mut TypeMap),
+) {
+    let op_state = runtime.op_state();
+    let mut op_state = op_state.borrow_mut();
+    with_state(&mut op_state, |op_state, ops: &mut Ops| {
+        with_state(op_state, |_, script_op_state: &mut TypeMap| {
+            for op in ops.iter() {
+                f(op.as_ref(), script_op_state);
+            }
+        });
+    });
+}
+
 /// Takes a [`serde_json::Value`] and converts all floating point number types that are safe
 /// integers, to integers.
 ///
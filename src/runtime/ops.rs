@@ -2,6 +2,7 @@ use super::OpMap;
 use crate::JsRuntimeOp;
 
 pub mod ecs;
+mod events;
 mod log;
 
 pub fn get_core_ops() -> OpMap {
@@ -13,6 +14,9 @@ pub fn get_core_ops() -> OpMap {
     // ECS
     ecs::insert_ecs_ops(&mut ops);
 
+    // Events
+    events::insert_event_ops(&mut ops);
+
     // Type defs
     ops.insert("typedefs", Box::new(TypesJs));
 
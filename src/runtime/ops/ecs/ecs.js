@@ -5,19 +5,168 @@
         index;
     }
 
+    // The entity objects that have been handed out, keyed by their id. An id is only reused with a
+    // new generation after the entity has been despawned, so this only keeps the latest entity of
+    // every id.
+    const entities = new Map();
+
+    // Entities are plain `{ id, generation }` values instead of value refs, so they stay valid
+    // across frames. The same entity is always the same object, so entities can be compared with
+    // `===` and used as `Map` keys.
     class Entity {
-        #bits;
         id;
         generation;
+
+        constructor(id, generation) {
+            this.id = id;
+            this.generation = generation;
+            Object.freeze(this);
+        }
+
+        static from({ id, generation }) {
+            const entity = entities.get(id);
+            if (entity && entity.generation == generation) {
+                return entity;
+            }
+
+            const newEntity = new Entity(id, generation);
+            entities.set(id, newEntity);
+            return newEntity;
+        }
+
+        toString() {
+            return `Entity(${this.id}v${this.generation})`;
+        }
     }
 
     class QueryItems extends Array {
         get(entity) {
-            const r = this.filter(x => x.entity.eq(entity))[0];
+            const r = this.filter(x => x.entity.id == entity.id && x.entity.generation == entity.generation)[0];
             return r && r.components;
         }
     }
 
+    // Query term for a component that is `null` in the results of the entities that don't have it
+    const Optional = (component) => ({ optional: component });
+
+    // Filters on the entities matched by a query, which are evaluated on the Rust side
+    const QueryFilter = {
+        with: (component) => ({ with: component }),
+        without: (component) => ({ without: component }),
+        changed: (component) => ({ changed: component }),
+        added: (component) => ({ added: component }),
+        or: (...filters) => ({ or: filters }),
+    };
+
+    // The number of query items fetched at once when iterating a query
+    const QUERY_CHUNK_SIZE = 64;
+
+    // Looks up the components of a filter of a prepared query
+    const prepareFilter = (filter) => {
+        const [, [preparedFilter]] = bevyModJsScriptingOpSync(
+            "ecs_world_query_prepare",
+            [],
+            [filter]
+        );
+        return preparedFilter;
+    };
+
+    function createQuery(parameters, filters, prepared = false) {
+        // Helper to collect the query results. The results are collected again every time that the
+        // query is used, so that they are never stale.
+        const collectQuery = (target) => QueryItems.from(bevyModJsScriptingOpSync(
+            "ecs_world_query_collect",
+            target.parameters,
+            target.filters,
+        ).map(({ entity, components }) => ({
+            entity: Entity.from(entity),
+            components: components.map(Value.wrapValueRef),
+        })));
+
+        const target = { parameters, filters, prepared };
+        return new Proxy(target, {
+            get(target, propName) {
+                switch (propName) {
+                    // Optimize the special case of accessing the components of a single entity.
+                    case "get":
+                        return (entity) => {
+                            let ret = bevyModJsScriptingOpSync(
+                                "ecs_world_query_get",
+                                entity,
+                                target.parameters,
+                                target.filters
+                            );
+                            return ret ? ret.map(Value.wrapValueRef) : undefined;
+                        };
+                    // Iterating the query streams its items from a cursor on the Rust side, so
+                    // value refs are only created for the items that are reached.
+                    case Symbol.iterator:
+                        return function* () {
+                            const cursor = bevyModJsScriptingOpSync(
+                                "ecs_world_query_iter",
+                                target.parameters,
+                                target.filters
+                            );
+                            try {
+                                while (true) {
+                                    const items = bevyModJsScriptingOpSync(
+                                        "ecs_world_query_next",
+                                        cursor,
+                                        QUERY_CHUNK_SIZE
+                                    );
+                                    if (items.length == 0) {
+                                        return;
+                                    }
+                                    for (const { entity, components } of items) {
+                                        yield {
+                                            entity: Entity.from(entity),
+                                            components: components.map(Value.wrapValueRef),
+                                        };
+                                    }
+                                }
+                            } finally {
+                                // Drop the cursor when the loop is exited early
+                                bevyModJsScriptingOpSync("ecs_world_query_drop", cursor);
+                            }
+                        };
+                    // Filters return a new query with the filter added
+                    case "with":
+                    case "without":
+                    case "changed":
+                    case "added":
+                    case "or":
+                        return (...args) => {
+                            let filter = QueryFilter[propName](...args);
+                            if (target.prepared) {
+                                filter = prepareFilter(filter);
+                            }
+                            return createQuery(
+                                target.parameters,
+                                [...target.filters, filter],
+                                target.prepared
+                            );
+                        };
+                    case "collect":
+                        return () => collectQuery(target);
+                    // Array methods collect all the query results once per call. The items can't
+                    // be accessed by index, which would collect the results on every access.
+                    default:
+                        const method = QueryItems.prototype[propName];
+                        if (typeof method == "function") {
+                            return (...args) => method.apply(collectQuery(target), args);
+                        }
+                        if (propName == "length" || /^\d+$/.test(String(propName))) {
+                            throw new TypeError(
+                                "Query items can only be accessed after collecting the query " +
+                                "with `collect()`, or by iterating it"
+                            );
+                        }
+                        return undefined;
+                }
+            }
+        })
+    }
+
     class World {
         toString() {
             return bevyModJsScriptingOpSync("ecs_world_to_string", this.rid);
@@ -32,7 +181,7 @@
         }
 
         get entities() {
-            return bevyModJsScriptingOpSync("ecs_world_entities", this.rid);
+            return bevyModJsScriptingOpSync("ecs_world_entities", this.rid).map(Entity.from);
         }
 
         resource(componentId) {
@@ -43,64 +192,88 @@
             return resource != null ? Value.wrapValueRef(resource) : null;
         }
 
+        insertResource(componentId, value) {
+            bevyModJsScriptingOpSync(
+                "ecs_world_insert_resource",
+                componentId,
+                Value.unwrapValueRef(value)
+            );
+        }
+
+        removeResource(componentId) {
+            bevyModJsScriptingOpSync("ecs_world_remove_resource", componentId);
+        }
+
+        hasResource(componentId) {
+            return bevyModJsScriptingOpSync("ecs_world_has_resource", componentId);
+        }
+
         query(...parameters) {
-            // Helper to collect and cache query results in the target
-            const collectedQuery = (target) => {
-                if (target.collected) {
-                    return target.collected;
-                } else {
-                    target.collected = QueryItems.from(bevyModJsScriptingOpSync(
-                        "ecs_world_query_collect",
-                        parameters,
-                    ).map(({ entity, components }) => ({
-                        entity: Value.wrapValueRef(entity),
-                        components: components.map(Value.wrapValueRef),
-                    })));
-
-                    return target.collected;
-                }
-            };
+            return createQuery(parameters, []);
+        }
 
-            const target = { parameters, collected: null };
-            return new Proxy(target, {
-                get(target, propName) {
-                    switch (propName) {
-                        // Optimize the special case of accessing the components of a single entity.
-                        case "get":
-                            return (entity) => {
-                                let ret = bevyModJsScriptingOpSync(
-                                    "ecs_world_query_get",
-                                    Value.unwrapValueRef(entity),
-                                    target.parameters
-                                );
-                                return ret ? ret.map(Value.wrapValueRef) : undefined;
-                            };
-                        // Default to collecting all the query results and returning the array prop.
-                        default:
-                            const collected = collectedQuery(target);
-                            const prop = collected[propName];
-                            return prop && prop.bind ? prop.bind(collected) : prop;
-                    }
-                }
-            })
+        // Create a query whose components are looked up once, to be kept and run in later frames.
+        // The filters added to the query are looked up once as well.
+        prepareQuery(...parameters) {
+            const [preparedParameters] = bevyModJsScriptingOpSync(
+                "ecs_world_query_prepare",
+                parameters,
+                []
+            );
+            return createQuery(preparedParameters, [], true);
         }
 
         get(entity, component) {
-            const r = bevyModJsScriptingOpSync("ecs_world_query_get", Value.unwrapValueRef(entity), [component]);
+            const r = bevyModJsScriptingOpSync("ecs_world_query_get", entity, [component], []);
             return r[0] && Value.wrapValueRef(r[0]);
         }
 
         insert(entity, component, value) {
             bevyModJsScriptingOpSync(
                 "ecs_component_insert",
-                Value.unwrapValueRef(entity),
+                entity,
                 component,
                 Value.unwrapValueRef(value)
             );
         }
 
         spawn() {
-            return Value.wrapValueRef(bevyModJsScriptingOpSync("ecs_entity_spawn"));
+            return Entity.from(bevyModJsScriptingOpSync("ecs_entity_spawn"));
+        }
+
+        despawn(entity, options) {
+            bevyModJsScriptingOpSync(
+                "ecs_entity_despawn",
+                entity,
+                options ?? null
+            );
+        }
+
+        remove(entity, component) {
+            bevyModJsScriptingOpSync(
+                "ecs_component_remove",
+                entity,
+                component
+            );
+        }
+
+        has(entity, component) {
+            return bevyModJsScriptingOpSync(
+                "ecs_component_has",
+                entity,
+                component
+            );
+        }
+
+        events(eventType) {
+            return {
+                read: () => bevyModJsScriptingOpSync("events_read", eventType)
+                    .map(Value.wrapValueRef),
+            };
+        }
+
+        sendEvent(eventType, value) {
+            bevyModJsScriptingOpSync("events_send", eventType, Value.unwrapValueRef(value));
         }
     }
 
@@ -110,6 +283,7 @@
         // tries to unwrap the inner value ref, otherwise returns the value unchanged
         unwrapValueRef(valueRefProxy) {
             if (valueRefProxy === null || valueRefProxy === undefined) return valueRefProxy;
+            if (valueRefProxy instanceof Entity) return valueRefProxy;
             const inner = valueRefProxy[VALUE_REF_GET_INNER]
             if (inner) {
                 return inner;
@@ -131,6 +305,11 @@
                 return valueRef;
             }
 
+            // entity leaves are returned as plain values
+            if ("generation" in valueRef && !("key" in valueRef)) {
+                return Entity.from(valueRef);
+            }
+
             let target = () => { };
             target.valueRef = valueRef;
             const proxy = new Proxy(target, {
@@ -198,9 +377,45 @@
 
         patch(value, patch) {
             Value.wrapValueRef(bevyModJsScriptingOpSync("ecs_value_ref_patch", Value.unwrapValueRef(value), patch));
+        },
+
+        // Keeps a value ref valid after the end of the frame, until it is released. Entities are
+        // always valid, so they don't need to be retained.
+        retain(value) {
+            if (!(value instanceof Entity)) {
+                bevyModJsScriptingOpSync("ecs_value_ref_retain", Value.unwrapValueRef(value));
+            }
+            return value;
+        },
+
+        // Lets a retained value ref be dropped at the end of the frame
+        release(value) {
+            if (!(value instanceof Entity)) {
+                bevyModJsScriptingOpSync("ecs_value_ref_release", Value.unwrapValueRef(value));
+            }
         }
     }
 
+    // Create the instance of a script module for an entity. The functions of entity scripts are
+    // called with `this` set to their instance.
+    window.bevyModJsScriptingCreateInstance = (exports, id, generation) => {
+        const instance = {};
+        Object.defineProperty(instance, "entity", { value: Entity.from({ id, generation }) });
+        bevyModJsScriptingUpdateInstance(instance, exports);
+        return instance;
+    };
+
+    // Make an instance inherit from the default export of a module if it is an object, or from
+    // the module's exports otherwise.
+    window.bevyModJsScriptingUpdateInstance = (instance, exports) => {
+        const prototype = typeof exports.default == "object" && exports.default !== null
+            ? exports.default
+            : exports;
+        Object.setPrototypeOf(instance, prototype);
+    };
+
     const world = new World();
     window.world = world;
+    window.QueryFilter = QueryFilter;
+    window.Optional = Optional;
 })(globalThis);
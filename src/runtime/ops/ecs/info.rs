@@ -1,11 +1,8 @@
-use bevy::{
-    ecs::component::ComponentId,
-    prelude::{default, Entity},
-};
+use bevy::{ecs::component::ComponentId, prelude::Entity};
 
-use crate::{runtime::OpContext, JsValueRef, JsValueRefs};
+use crate::runtime::OpContext;
 
-use super::types::JsComponentInfo;
+use super::types::{JsComponentInfo, JsEntity};
 
 pub fn ecs_world_to_string(
     _context: OpContext,
@@ -46,19 +43,14 @@ pub fn ecs_world_resources(
 }
 
 pub fn ecs_world_entities(
-    context: OpContext,
+    _context: OpContext,
     world: &mut bevy::prelude::World,
     _args: serde_json::Value,
 ) -> anyhow::Result<serde_json::Value> {
-    let value_refs = context
-        .op_state
-        .entry::<JsValueRefs>()
-        .or_insert_with(default);
-
     let entities = world
         .query::<Entity>()
         .iter(world)
-        .map(|e| JsValueRef::new_free(Box::new(e), value_refs))
+        .map(JsEntity::from)
         .collect::<Vec<_>>();
 
     Ok(serde_json::to_value(entities)?)
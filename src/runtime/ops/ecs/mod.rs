@@ -22,10 +22,42 @@ pub fn insert_ecs_ops(ops: &mut OpMap) {
         Box::new(query::ecs_world_query_collect),
     );
     ops.insert("ecs_world_query_get", Box::new(query::ecs_world_query_get));
+    ops.insert(
+        "ecs_world_query_prepare",
+        Box::new(query::ecs_world_query_prepare),
+    );
+    ops.insert(
+        "ecs_world_query_iter",
+        Box::new(query::ecs_world_query_iter),
+    );
+    ops.insert(
+        "ecs_world_query_next",
+        Box::new(query::ecs_world_query_next),
+    );
+    ops.insert(
+        "ecs_world_query_drop",
+        Box::new(query::ecs_world_query_drop),
+    );
+    ops.insert(
+        "ecs_world_query_cleanup",
+        Box::new(query::QueryCursorCleanup),
+    );
     ops.insert(
         "ecs_world_get_resource",
         Box::new(resource::ecs_world_get_resource),
     );
+    ops.insert(
+        "ecs_world_insert_resource",
+        Box::new(resource::ecs_world_insert_resource),
+    );
+    ops.insert(
+        "ecs_world_remove_resource",
+        Box::new(resource::ecs_world_remove_resource),
+    );
+    ops.insert(
+        "ecs_world_has_resource",
+        Box::new(resource::ecs_world_has_resource),
+    );
     ops.insert("ecs_value_ref_get", Box::new(value::ecs_value_ref_get));
     ops.insert("ecs_value_ref_set", Box::new(value::ecs_value_ref_set));
     ops.insert("ecs_value_ref_keys", Box::new(value::ecs_value_ref_keys));
@@ -40,13 +72,26 @@ pub fn insert_ecs_ops(ops: &mut OpMap) {
         Box::new(value::ecs_value_ref_default),
     );
     ops.insert("ecs_value_ref_patch", Box::new(value::ecs_value_ref_patch));
+    ops.insert(
+        "ecs_value_ref_retain",
+        Box::new(value::ecs_value_ref_retain),
+    );
+    ops.insert(
+        "ecs_value_ref_release",
+        Box::new(value::ecs_value_ref_release),
+    );
     ops.insert("ecs_value_ref_cleanup", Box::new(value::EcsValueRefCleanup));
     ops.insert("ecs_entity_spawn", Box::new(world::ecs_entity_spawn));
-    ops.insert("ecs_value_ref_cleanup", Box::new(value::EcsValueRefCleanup));
     ops.insert(
         "ecs_component_insert",
         Box::new(world::ecs_component_insert),
     );
+    ops.insert("ecs_entity_despawn", Box::new(world::ecs_entity_despawn));
+    ops.insert(
+        "ecs_component_remove",
+        Box::new(world::ecs_component_remove),
+    );
+    ops.insert("ecs_component_has", Box::new(world::ecs_component_has));
 }
 
 /// Op used to provide the JS classes and globals used to interact with the other ECS ops
@@ -1,15 +1,382 @@
-use anyhow::Context;
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::{format_err, Context};
 use bevy::{
-    ecs::component::ComponentId,
-    prelude::{default, Entity},
+    ecs::{
+        archetype::{Archetype, ArchetypeGeneration, ArchetypeId},
+        component::ComponentId,
+    },
+    prelude::{default, Entity, Handle, World},
+    reflect::TypeRegistry,
+    utils::HashMap,
+};
+use bevy_ecs_dynamic::reflect_value_ref::{query::EcsValueRefQuery, EcsValueRef};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    asset::JsScript,
+    runtime::{JsRuntimeOp, OpContext},
 };
-use bevy_ecs_dynamic::reflect_value_ref::query::EcsValueRefQuery;
 
-use crate::runtime::OpContext;
+use super::types::{ComponentIdOrBevyType, JsEntity, JsQueryItem, JsValueRef, JsValueRefs};
+
+pub type QueryDescriptor = Vec<QueryTerm>;
+
+/// A component of a query, which entities either must have, or which is `null` in the results
+/// for the entities that don't have it
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum QueryTerm {
+    Optional { optional: ComponentIdOrBevyType },
+    Required(ComponentIdOrBevyType),
+}
+
+/// A filter on the entities matched by a query
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QueryFilter {
+    With(ComponentIdOrBevyType),
+    Without(ComponentIdOrBevyType),
+    /// Matches entities whose component changed since the previous run of the script
+    Changed(ComponentIdOrBevyType),
+    /// Matches entities whose component was added since the previous run of the script
+    Added(ComponentIdOrBevyType),
+    /// Matches entities matching any of the filters
+    Or(Vec<QueryFilter>),
+}
+
+/// A [`QueryFilter`] with its components looked up in the world
+#[derive(Clone)]
+enum ResolvedQueryFilter {
+    With(ComponentId),
+    Without(ComponentId),
+    Changed(ComponentId),
+    Added(ComponentId),
+    Or(Vec<ResolvedQueryFilter>),
+    /// A filter on a component that doesn't have an id in the world yet, and which no entity can
+    /// have
+    Constant(bool),
+}
+
+impl QueryFilter {
+    /// Looks up the component ids of the filter, keeping the types of the components that don't
+    /// have an id yet
+    fn prepare(self, world: &World, type_registry: &TypeRegistry) -> anyhow::Result<Self> {
+        let prepare = |ty| prepare_component(ty, world, type_registry);
+        Ok(match self {
+            QueryFilter::With(ty) => QueryFilter::With(prepare(ty)?),
+            QueryFilter::Without(ty) => QueryFilter::Without(prepare(ty)?),
+            QueryFilter::Changed(ty) => QueryFilter::Changed(prepare(ty)?),
+            QueryFilter::Added(ty) => QueryFilter::Added(prepare(ty)?),
+            QueryFilter::Or(filters) => QueryFilter::Or(
+                filters
+                    .into_iter()
+                    .map(|filter| filter.prepare(world, type_registry))
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+
+    fn resolve(
+        &self,
+        world: &World,
+        type_registry: &TypeRegistry,
+    ) -> anyhow::Result<ResolvedQueryFilter> {
+        // No entity can have a component that hasn't been given an id yet, but the type should
+        // at least exist.
+        let component_id = |ty: &ComponentIdOrBevyType| {
+            ty.registration(world, type_registry)?;
+            Ok::<_, anyhow::Error>(ty.component_id(world, type_registry).ok())
+        };
+
+        Ok(match self {
+            QueryFilter::With(ty) => component_id(ty)?.map_or(
+                ResolvedQueryFilter::Constant(false),
+                ResolvedQueryFilter::With,
+            ),
+            QueryFilter::Without(ty) => component_id(ty)?.map_or(
+                ResolvedQueryFilter::Constant(true),
+                ResolvedQueryFilter::Without,
+            ),
+            QueryFilter::Changed(ty) => component_id(ty)?.map_or(
+                ResolvedQueryFilter::Constant(false),
+                ResolvedQueryFilter::Changed,
+            ),
+            QueryFilter::Added(ty) => component_id(ty)?.map_or(
+                ResolvedQueryFilter::Constant(false),
+                ResolvedQueryFilter::Added,
+            ),
+            QueryFilter::Or(filters) => ResolvedQueryFilter::Or(
+                filters
+                    .iter()
+                    .map(|filter| filter.resolve(world, type_registry))
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+}
+
+impl ResolvedQueryFilter {
+    /// Returns whether the filter only depends on the archetype of the entities
+    fn is_archetype_filter(&self) -> bool {
+        match self {
+            ResolvedQueryFilter::With(_)
+            | ResolvedQueryFilter::Without(_)
+            | ResolvedQueryFilter::Constant(_) => true,
+            ResolvedQueryFilter::Changed(_) | ResolvedQueryFilter::Added(_) => false,
+            ResolvedQueryFilter::Or(filters) => filters.iter().all(Self::is_archetype_filter),
+        }
+    }
+
+    /// Returns whether the entities of an archetype can match the filter. Change filters match
+    /// the archetypes with their component, and are checked for each entity.
+    fn matches_archetype(&self, archetype: &Archetype) -> bool {
+        match self {
+            ResolvedQueryFilter::With(component_id)
+            | ResolvedQueryFilter::Changed(component_id)
+            | ResolvedQueryFilter::Added(component_id) => archetype.contains(*component_id),
+            ResolvedQueryFilter::Without(component_id) => !archetype.contains(*component_id),
+            ResolvedQueryFilter::Or(filters) => filters
+                .iter()
+                .any(|filter| filter.matches_archetype(archetype)),
+            ResolvedQueryFilter::Constant(value) => *value,
+        }
+    }
+
+    /// Returns whether an entity matches the filter. Change filters match the changes made since
+    /// the given change tick.
+    fn matches_entity(&self, world: &World, entity: Entity, last_run_tick: u32) -> bool {
+        let change_tick = world.read_change_tick();
+        let ticks = |component_id: &ComponentId| {
+            world
+                .get_entity(entity)
+                .and_then(|entity| entity.get_change_ticks_by_id(*component_id))
+        };
+
+        match self {
+            ResolvedQueryFilter::With(component_id) => world
+                .get_entity(entity)
+                .map_or(false, |entity| entity.contains_id(*component_id)),
+            ResolvedQueryFilter::Without(component_id) => world
+                .get_entity(entity)
+                .map_or(false, |entity| !entity.contains_id(*component_id)),
+            ResolvedQueryFilter::Changed(component_id) => ticks(component_id)
+                .map_or(false, |ticks| ticks.is_changed(last_run_tick, change_tick)),
+            ResolvedQueryFilter::Added(component_id) => ticks(component_id)
+                .map_or(false, |ticks| ticks.is_added(last_run_tick, change_tick)),
+            ResolvedQueryFilter::Or(filters) => filters
+                .iter()
+                .any(|filter| filter.matches_entity(world, entity, last_run_tick)),
+            ResolvedQueryFilter::Constant(value) => *value,
+        }
+    }
+}
+
+/// A query with its components and filters looked up in the world
+struct ResolvedQuery {
+    /// The required components and their query
+    required_components: Vec<ComponentId>,
+    query: SharedQuery,
+    /// The number of components in the query items
+    len: usize,
+    /// The index of each required component in the query items
+    required: Vec<usize>,
+    /// The index of each optional component in the query items, along with the component and its
+    /// query, or `None` if the component doesn't have an id in the world yet
+    optional: Vec<(usize, Option<SharedQuery>)>,
+    filters: Vec<ResolvedQueryFilter>,
+}
+
+impl ResolvedQuery {
+    /// Resolves a query, getting its compiled queries from the cache
+    fn new(
+        descriptor: &[QueryTerm],
+        filters: &[QueryFilter],
+        world: &mut World,
+        type_registry: &TypeRegistry,
+        cache: &mut QueryCache,
+    ) -> anyhow::Result<Self> {
+        let mut required_components = Vec::new();
+        let mut required = Vec::new();
+        let mut optional = Vec::new();
+        for (idx, term) in descriptor.iter().enumerate() {
+            match term {
+                QueryTerm::Required(ty) => {
+                    required_components.push(ty.component_id(world, type_registry)?);
+                    required.push(idx);
+                }
+                QueryTerm::Optional { optional: ty } => {
+                    // No entity can have a component that hasn't been given an id yet, but the
+                    // type should at least exist.
+                    let query = match ty.component_id(world, type_registry) {
+                        Ok(component_id) => Some(cache.get(world, &[component_id])),
+                        Err(_) => {
+                            ty.registration(world, type_registry)?;
+                            None
+                        }
+                    };
+                    optional.push((idx, query));
+                }
+            }
+        }
+
+        let filters = filters
+            .iter()
+            .map(|filter| filter.resolve(world, type_registry))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            query: cache.get(world, &required_components),
+            required_components,
+            len: descriptor.len(),
+            required,
+            optional,
+            filters,
+        })
+    }
+
+    /// Returns whether the entities of an archetype can match the query
+    fn matches_archetype(&self, archetype: &Archetype) -> bool {
+        self.required_components
+            .iter()
+            .all(|component_id| archetype.contains(*component_id))
+            && self
+                .filters
+                .iter()
+                .all(|filter| filter.matches_archetype(archetype))
+    }
+
+    /// Returns the entities of the archetypes that can match the query
+    fn archetype_entities(&self, world: &World) -> Vec<Entity> {
+        world
+            .archetypes()
+            .iter()
+            .filter(|archetype| self.matches_archetype(archetype))
+            .flat_map(|archetype| archetype.entities().iter().map(|entity| entity.entity()))
+            .collect()
+    }
+
+    /// Returns the first archetype from the given index on that can match the query, with its
+    /// index and entities
+    fn next_archetype(
+        &self,
+        world: &World,
+        start: usize,
+    ) -> Option<(usize, ArchetypeId, Vec<Entity>)> {
+        world
+            .archetypes()
+            .iter()
+            .enumerate()
+            .skip(start)
+            .find(|(_, archetype)| self.matches_archetype(archetype))
+            .map(|(idx, archetype)| {
+                let entities = archetype
+                    .entities()
+                    .iter()
+                    .map(|entity| entity.entity())
+                    .collect();
+                (idx, archetype.id(), entities)
+            })
+    }
+
+    /// Keeps the entities matching the change filters of the query, which are relative to the
+    /// given change tick
+    fn filter_changes(
+        &self,
+        world: &World,
+        entities: Vec<Entity>,
+        last_run_tick: u32,
+    ) -> Vec<Entity> {
+        let filters = self
+            .filters
+            .iter()
+            .filter(|filter| !filter.is_archetype_filter())
+            .collect::<Vec<_>>();
+        if filters.is_empty() {
+            return entities;
+        }
 
-use super::types::{ComponentIdOrBevyType, JsQueryItem, JsValueRef, JsValueRefs};
+        entities
+            .into_iter()
+            .filter(|&entity| {
+                filters
+                    .iter()
+                    .all(|filter| filter.matches_entity(world, entity, last_run_tick))
+            })
+            .collect()
+    }
 
-pub type QueryDescriptor = Vec<ComponentIdOrBevyType>;
+    /// Keeps the entities that still match the query
+    fn filter_entities(
+        &self,
+        world: &World,
+        entities: Vec<Entity>,
+        last_run_tick: u32,
+    ) -> Vec<Entity> {
+        let entities = entities
+            .into_iter()
+            .filter(|&entity| {
+                world
+                    .get_entity(entity)
+                    .map_or(false, |entity| self.matches_archetype(entity.archetype()))
+            })
+            .collect();
+        self.filter_changes(world, entities, last_run_tick)
+    }
+
+    /// Puts the values of the required components of an entity in the order of the query, along
+    /// with the values of its optional components
+    fn item_components(
+        &mut self,
+        world: &mut World,
+        entity: Entity,
+        required_values: Vec<EcsValueRef>,
+    ) -> Vec<Option<EcsValueRef>> {
+        let mut components = (0..self.len).map(|_| None).collect::<Vec<_>>();
+        for (&idx, value) in self.required.iter().zip(required_values) {
+            components[idx] = Some(value);
+        }
+        for (idx, query) in &mut self.optional {
+            components[*idx] = query
+                .as_mut()
+                .and_then(|query| query.borrow_mut().get(world, entity).ok())
+                .and_then(|values| values.into_iter().next());
+        }
+        components
+    }
+
+    /// Returns the components of an entity that has already been filtered
+    fn item(&mut self, world: &mut World, entity: Entity) -> Option<Vec<Option<EcsValueRef>>> {
+        let required_values = self.query.borrow_mut().get(world, entity).ok()?;
+        Some(self.item_components(world, entity, required_values))
+    }
+
+    /// Returns the entities matching the query, with their components
+    fn collect(
+        &mut self,
+        world: &mut World,
+        last_run_tick: u32,
+    ) -> Vec<(Entity, Vec<Option<EcsValueRef>>)> {
+        let entities = self.archetype_entities(world);
+        self.filter_changes(world, entities, last_run_tick)
+            .into_iter()
+            .filter_map(|entity| Some((entity, self.item(world, entity)?)))
+            .collect()
+    }
+
+    /// Returns the components of an entity, if it matches the query
+    fn get(
+        &mut self,
+        world: &mut World,
+        entity: Entity,
+        last_run_tick: u32,
+    ) -> Option<Vec<Option<EcsValueRef>>> {
+        let entity = self
+            .filter_entities(world, vec![entity], last_run_tick)
+            .pop()?;
+        self.item(world, entity)
+    }
+}
 
 /// Queries world and collects results into a JS array
 pub fn ecs_world_query_collect(
@@ -17,33 +384,28 @@ pub fn ecs_world_query_collect(
     world: &mut bevy::prelude::World,
     args: serde_json::Value,
 ) -> anyhow::Result<serde_json::Value> {
+    let (descriptor, filters): (QueryDescriptor, Vec<QueryFilter>) =
+        serde_json::from_value(args).context("Parse world query descriptor")?;
+
+    let cache = context
+        .op_state
+        .entry::<QueryCache>()
+        .or_insert_with(default);
+    let mut query = ResolvedQuery::new(&descriptor, &filters, world, context.type_registry, cache)?;
+    let items = query.collect(world, context.script_info.last_run_tick);
+
     let value_refs = context
         .op_state
         .entry::<JsValueRefs>()
         .or_insert_with(default);
-
-    let (descriptor,): (QueryDescriptor,) =
-        serde_json::from_value(args).context("Parse world query descriptor")?;
-
-    let components: Vec<ComponentId> = descriptor
-        .iter()
-        .map(|ty| ty.component_id(world, context.type_registry))
-        .collect::<Result<_, _>>()?;
-
-    let mut query = EcsValueRefQuery::new(world, &components);
-    let results = query
-        .iter(world)
-        .map(|item| {
-            let components = item
-                .items
+    let results = items
+        .into_iter()
+        .map(|(entity, components)| JsQueryItem {
+            entity: entity.into(),
+            components: components
                 .into_iter()
-                .map(|value| JsValueRef::new_ecs(value, value_refs))
-                .collect();
-
-            JsQueryItem {
-                entity: JsValueRef::new_free(Box::new(item.entity), value_refs),
-                components,
-            }
+                .map(|value| value.map(|value| JsValueRef::new_ecs(value, value_refs)))
+                .collect(),
         })
         .collect::<Vec<_>>();
 
@@ -56,30 +418,279 @@ pub fn ecs_world_query_get(
     world: &mut bevy::prelude::World,
     args: serde_json::Value,
 ) -> anyhow::Result<serde_json::Value> {
+    let (entity, descriptor, filters): (JsEntity, QueryDescriptor, Vec<QueryFilter>) =
+        serde_json::from_value(args).context("component query")?;
+    let entity = Entity::from(entity);
+
+    let cache = context
+        .op_state
+        .entry::<QueryCache>()
+        .or_insert_with(default);
+    let mut query = ResolvedQuery::new(&descriptor, &filters, world, context.type_registry, cache)?;
+    let components = query.get(world, entity, context.script_info.last_run_tick);
+
     let value_refs = context
         .op_state
         .entry::<JsValueRefs>()
         .or_insert_with(default);
+    let result = components.map(|components| {
+        components
+            .into_iter()
+            .map(|value| value.map(|value| JsValueRef::new_ecs(value, value_refs)))
+            .collect::<Vec<_>>()
+    });
 
-    let (entity_value_ref, descriptor): (JsValueRef, QueryDescriptor) =
-        serde_json::from_value(args).context("component query")?;
-    let entity = entity_value_ref.get_downcast_copy::<Entity>(world, value_refs)?;
-
-    let components: Vec<ComponentId> = descriptor
-        .iter()
-        .map(|ty| ty.component_id(world, context.type_registry))
-        .collect::<Result<_, _>>()?;
-
-    let mut query = EcsValueRefQuery::new(world, &components);
-    let result = query
-        .get(world, entity)
-        .map(|components| {
-            components
+    Ok(serde_json::to_value(result)?)
+}
+
+/// The cursors of the queries being iterated by scripts
+///
+/// Cursors are dropped at the end of the frame, since the value refs they hand out are only valid
+/// during the frame.
+#[derive(Default)]
+struct QueryCursors {
+    next_id: u32,
+    cursors: HashMap<u32, QueryCursor>,
+}
+
+struct QueryCursor {
+    /// The script iterating the query
+    script: Handle<JsScript>,
+    query: ResolvedQuery,
+    /// The index of the next archetype to look for entities matching the query in
+    next_archetype: usize,
+    /// The archetype being iterated, and its entities that haven't been returned
+    archetype: Option<ArchetypeId>,
+    entities: std::vec::IntoIter<Entity>,
+}
+
+/// Starts iterating a query, returning the id of the cursor to pass to `ecs_world_query_next`
+pub fn ecs_world_query_iter(
+    context: OpContext,
+    world: &mut bevy::prelude::World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (descriptor, filters): (QueryDescriptor, Vec<QueryFilter>) =
+        serde_json::from_value(args).context("Parse world query descriptor")?;
+
+    let cache = context
+        .op_state
+        .entry::<QueryCache>()
+        .or_insert_with(default);
+    let query = ResolvedQuery::new(&descriptor, &filters, world, context.type_registry, cache)?;
+
+    let cursors = context
+        .op_state
+        .entry::<QueryCursors>()
+        .or_insert_with(default);
+    let id = cursors.next_id;
+    cursors.next_id += 1;
+    cursors.cursors.insert(
+        id,
+        QueryCursor {
+            script: context.script_info.handle.clone_weak(),
+            query,
+            next_archetype: 0,
+            archetype: None,
+            entities: Vec::new().into_iter(),
+        },
+    );
+
+    Ok(serde_json::to_value(id)?)
+}
+
+/// Returns the next items of a query cursor, up to the given count. An empty result means that
+/// the query has been exhausted, and the cursor is dropped.
+pub fn ecs_world_query_next(
+    context: OpContext,
+    world: &mut bevy::prelude::World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (id, count): (u32, usize) = serde_json::from_value(args).context("parse args")?;
+    let last_run_tick = context.script_info.last_run_tick;
+
+    // The queries of the cursor need the archetypes added since the previous chunk
+    context
+        .op_state
+        .entry::<QueryCache>()
+        .or_insert_with(default)
+        .update_archetypes(world);
+
+    let cursors = context
+        .op_state
+        .entry::<QueryCursors>()
+        .or_insert_with(default);
+    let cursor = cursors
+        .cursors
+        .get_mut(&id)
+        .ok_or_else(|| format_err!("Query cursor doesn't exist"))?;
+
+    // The archetypes matching the query are only looked up as the iteration reaches them, and
+    // their entities are filtered a chunk at a time. Entities despawned or moved to another
+    // archetype since the iteration reached their archetype are skipped.
+    let mut items = Vec::new();
+    while items.len() < count {
+        let mut chunk = cursor
+            .entities
+            .by_ref()
+            .take(count - items.len())
+            .collect::<Vec<_>>();
+        if chunk.is_empty() {
+            match cursor.query.next_archetype(world, cursor.next_archetype) {
+                Some((idx, archetype, entities)) => {
+                    cursor.next_archetype = idx + 1;
+                    cursor.archetype = Some(archetype);
+                    cursor.entities = entities.into_iter();
+                    continue;
+                }
+                None => break,
+            }
+        }
+
+        chunk.retain(|&entity| {
+            world.get_entity(entity).map_or(false, |entity| {
+                Some(entity.archetype().id()) == cursor.archetype
+            })
+        });
+        for entity in cursor.query.filter_changes(world, chunk, last_run_tick) {
+            if let Some(components) = cursor.query.item(world, entity) {
+                items.push((entity, components));
+            }
+        }
+    }
+    if items.is_empty() {
+        cursors.cursors.remove(&id);
+    }
+
+    let value_refs = context
+        .op_state
+        .entry::<JsValueRefs>()
+        .or_insert_with(default);
+    let results = items
+        .into_iter()
+        .map(|(entity, components)| JsQueryItem {
+            entity: entity.into(),
+            components: components
                 .into_iter()
-                .map(|value| JsValueRef::new_ecs(value, value_refs))
-                .collect::<Vec<_>>()
+                .map(|value| value.map(|value| JsValueRef::new_ecs(value, value_refs)))
+                .collect(),
         })
-        .ok();
+        .collect::<Vec<_>>();
 
-    Ok(serde_json::to_value(result)?)
+    Ok(serde_json::to_value(results)?)
+}
+
+/// Drops a query cursor before it has been exhausted
+pub fn ecs_world_query_drop(
+    context: OpContext,
+    world: &mut bevy::prelude::World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (id,): (u32,) = serde_json::from_value(args).context("parse args")?;
+
+    if let Some(cursors) = context.op_state.get_mut::<QueryCursors>() {
+        cursors.cursors.remove(&id);
+    }
+
+    Ok(serde_json::Value::Null)
+}
+
+pub struct QueryCursorCleanup;
+
+impl JsRuntimeOp for QueryCursorCleanup {
+    fn frame_end(&self, op_state: &mut type_map::TypeMap, _world: &mut World) {
+        if let Some(cursors) = op_state.get_mut::<QueryCursors>() {
+            cursors.cursors.clear();
+        }
+    }
+
+    fn script_unloaded(&self, op_state: &mut type_map::TypeMap, script: &Handle<JsScript>) {
+        if let Some(cursors) = op_state.get_mut::<QueryCursors>() {
+            cursors.cursors.retain(|_, cursor| &cursor.script != script);
+        }
+    }
+}
+
+/// A compiled query, shared by the cache and the queries using it
+type SharedQuery = Rc<RefCell<EcsValueRefQuery>>;
+
+/// The compiled queries kept across frames, keyed by the ids of their components
+///
+/// The queries are updated with the archetypes added to the world before they are used, so that
+/// they don't miss the entities in them.
+#[derive(Default)]
+struct QueryCache {
+    archetype_generation: Option<ArchetypeGeneration>,
+    queries: HashMap<Vec<ComponentId>, SharedQuery>,
+}
+
+impl QueryCache {
+    /// Returns the compiled query of the components, compiling it if it isn't cached
+    fn get(&mut self, world: &mut World, components: &[ComponentId]) -> SharedQuery {
+        self.update_archetypes(world);
+
+        self.queries
+            .entry(components.to_vec())
+            .or_insert_with(|| Rc::new(RefCell::new(EcsValueRefQuery::new(world, components))))
+            .clone()
+    }
+
+    /// Updates the cached queries with the archetypes added to the world since they were last
+    /// updated
+    fn update_archetypes(&mut self, world: &World) {
+        let generation = world.archetypes().generation();
+        if self.archetype_generation != Some(generation) {
+            for query in self.queries.values() {
+                query.borrow_mut().update_archetypes(world);
+            }
+            self.archetype_generation = Some(generation);
+        }
+    }
+}
+
+/// Looks up the id of a component, keeping the type of a component that doesn't have an id yet
+fn prepare_component(
+    ty: ComponentIdOrBevyType,
+    world: &World,
+    type_registry: &TypeRegistry,
+) -> anyhow::Result<ComponentIdOrBevyType> {
+    match ty.component_id(world, type_registry) {
+        Ok(component_id) => Ok(ComponentIdOrBevyType::ComponentId(component_id.into())),
+        Err(_) => {
+            ty.registration(world, type_registry)?;
+            Ok(ty)
+        }
+    }
+}
+
+/// Resolves the components and filters of a query to their ids, so that a prepared query doesn't
+/// need to look them up by name every time it is run
+pub fn ecs_world_query_prepare(
+    context: OpContext,
+    world: &mut bevy::prelude::World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (descriptor, filters): (QueryDescriptor, Vec<QueryFilter>) =
+        serde_json::from_value(args).context("Parse world query descriptor")?;
+
+    let descriptor = descriptor
+        .into_iter()
+        .map(|term| {
+            Ok(match term {
+                QueryTerm::Required(ty) => QueryTerm::Required(ComponentIdOrBevyType::ComponentId(
+                    ty.component_id(world, context.type_registry)?.into(),
+                )),
+                // Optional components that don't have an id yet keep being looked up by name
+                QueryTerm::Optional { optional: ty } => QueryTerm::Optional {
+                    optional: prepare_component(ty, world, context.type_registry)?,
+                },
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let filters = filters
+        .into_iter()
+        .map(|filter| filter.prepare(world, context.type_registry))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(serde_json::to_value((descriptor, filters))?)
 }
@@ -1,5 +1,8 @@
-use anyhow::Context;
-use bevy::prelude::default;
+use anyhow::{format_err, Context};
+use bevy::{
+    prelude::{default, ReflectResource, World},
+    reflect::TypeRegistry,
+};
 use bevy_ecs_dynamic::reflect_value_ref::{EcsValueRef, ReflectValueRef};
 
 use crate::runtime::OpContext;
@@ -29,3 +32,75 @@ pub fn ecs_world_get_resource(
 
     Ok(serde_json::to_value(value_ref)?)
 }
+
+pub fn ecs_world_insert_resource(
+    context: OpContext,
+    world: &mut bevy::prelude::World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (ty, value_ref): (ComponentIdOrBevyType, JsValueRef) =
+        serde_json::from_value(args).context("parse args")?;
+    let reflect_resource = reflect_resource(&ty, world, context.type_registry)?;
+
+    let value_refs = context
+        .op_state
+        .entry::<JsValueRefs>()
+        .or_insert_with(default);
+
+    // Clone the reflect value of the resource
+    let reflect_value = value_refs
+        .get(value_ref.key)
+        .ok_or_else(|| format_err!("Value ref doesn't exist"))?
+        .get(world)?
+        .clone_value();
+
+    reflect_resource.apply_or_insert(world, &*reflect_value);
+
+    Ok(serde_json::Value::Null)
+}
+
+pub fn ecs_world_remove_resource(
+    context: OpContext,
+    world: &mut bevy::prelude::World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (ty,): (ComponentIdOrBevyType,) = serde_json::from_value(args).context("parse args")?;
+    let reflect_resource = reflect_resource(&ty, world, context.type_registry)?;
+
+    reflect_resource.remove(world);
+
+    Ok(serde_json::Value::Null)
+}
+
+pub fn ecs_world_has_resource(
+    context: OpContext,
+    world: &mut bevy::prelude::World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (ty,): (ComponentIdOrBevyType,) = serde_json::from_value(args).context("parse args")?;
+    let reflect_resource = reflect_resource(&ty, world, context.type_registry)?;
+
+    Ok(serde_json::to_value(
+        reflect_resource.reflect(world).is_some(),
+    )?)
+}
+
+/// Gets the [`ReflectResource`] of a resource type
+fn reflect_resource(
+    ty: &ComponentIdOrBevyType,
+    world: &World,
+    type_registry: &TypeRegistry,
+) -> anyhow::Result<ReflectResource> {
+    let registration = ty.registration(world, type_registry)?;
+    let reflect_resource = registration
+        .data::<ReflectResource>()
+        .ok_or_else(|| {
+            format_err!(
+                "ReflectResource not found for `{}`",
+                registration.type_name()
+            )
+        })?
+        .clone();
+
+    Ok(reflect_resource)
+}
@@ -1,6 +1,5 @@
 use std::{cell::RefCell, rc::Rc};
 
-use anyhow::format_err;
 use bevy::{
     ecs::component::{ComponentId, ComponentInfo},
     prelude::*,
@@ -49,32 +48,37 @@ impl JsValueRef {
             function: None,
         }
     }
+}
 
-    /// If this value ref represents an [`Entity`] get it. Returns an error if it is not an entity.
-    pub fn get_downcast_copy<T: Reflect + Copy>(
-        &self,
-        world: &World,
-        value_refs: &JsValueRefs,
-    ) -> anyhow::Result<T> {
-        let value_ref: &ReflectValueRef = value_refs
-            .get(self.key)
-            .ok_or_else(|| format_err!("Value ref doesn't exist"))?;
-
-        let borrow = value_ref.get(world)?;
-
-        Ok(*borrow
-            .downcast_ref()
-            .ok_or_else(|| format_err!("Value passed not an entity"))?)
+/// An [`Entity`] as it is passed to and from JS, as a plain `{ id, generation }` object that
+/// doesn't need a value ref
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsEntity {
+    pub id: u32,
+    pub generation: u32,
+}
+impl From<Entity> for JsEntity {
+    fn from(entity: Entity) -> Self {
+        JsEntity {
+            id: entity.index(),
+            generation: entity.generation(),
+        }
+    }
+}
+impl From<JsEntity> for Entity {
+    fn from(entity: JsEntity) -> Self {
+        Entity::from_bits((entity.generation as u64) << 32 | entity.id as u64)
     }
 }
 
 #[derive(Serialize)]
 pub struct JsQueryItem {
-    pub entity: JsValueRef,
-    pub components: Vec<JsValueRef>,
+    pub entity: JsEntity,
+    /// The values of the components, which are `None` for missing optional components
+    pub components: Vec<Option<JsValueRef>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum ComponentIdOrBevyType {
     ComponentId(JsComponentId),
@@ -173,6 +177,7 @@ impl From<&ComponentInfo> for JsComponentInfo {
 pub enum ReflectArgIntermediate<'a> {
     Value(ReflectArgIntermediateValue<'a>),
     Primitive(Primitive, PassMode),
+    Entity(Entity, PassMode),
 }
 
 pub enum ReflectArgIntermediateValue<'a> {
@@ -196,6 +201,7 @@ impl<'a> ReflectArgIntermediate<'a> {
         match self {
             ReflectArgIntermediate::Value(val) => val.as_arg(),
             ReflectArgIntermediate::Primitive(prim, pass_mode) => prim.as_arg(*pass_mode),
+            ReflectArgIntermediate::Entity(entity, pass_mode) => reflect_arg(entity, *pass_mode),
         }
     }
 }
@@ -219,10 +225,14 @@ impl Primitive {
             Primitive::u32(val) => val,
         };
 
-        match pass_mode {
-            PassMode::Ref => ReflectArg::Ref(reflect),
-            PassMode::RefMut => ReflectArg::RefMut(reflect),
-            PassMode::Owned => ReflectArg::Owned(reflect),
-        }
+        reflect_arg(reflect, pass_mode)
+    }
+}
+
+fn reflect_arg(reflect: &mut dyn Reflect, pass_mode: PassMode) -> ReflectArg<'_> {
+    match pass_mode {
+        PassMode::Ref => ReflectArg::Ref(reflect),
+        PassMode::RefMut => ReflectArg::RefMut(reflect),
+        PassMode::Owned => ReflectArg::Owned(reflect),
     }
 }
@@ -2,19 +2,19 @@ use std::any::TypeId;
 
 use anyhow::{bail, format_err, Context};
 use bevy::{
-    prelude::{default, ReflectDefault, World},
-    utils::HashMap,
+    prelude::{default, Deref, DerefMut, Entity, Handle, ReflectDefault, World},
+    utils::{HashMap, HashSet},
 };
 use bevy_ecs_dynamic::reflect_value_ref::ReflectValueRef;
 use bevy_reflect::{Reflect, ReflectRef};
 use bevy_reflect_fns::{PassMode, ReflectArg, ReflectMethods};
 
-use crate::{runtime::OpContext, JsReflectFunctions, JsRuntimeOp};
+use crate::{asset::JsScript, runtime::OpContext, JsReflectFunctions, JsRuntimeOp};
 
 use super::{
     types::{
-        ComponentIdOrBevyType, JsValueRef, JsValueRefs, Primitive, ReflectArgIntermediate,
-        ReflectArgIntermediateValue,
+        ComponentIdOrBevyType, JsEntity, JsValueRef, JsValueRefKey, JsValueRefs, Primitive,
+        ReflectArgIntermediate, ReflectArgIntermediateValue, ReflectFunctionKey,
     },
     WithValueRefs,
 };
@@ -185,6 +185,20 @@ pub fn patch_reflect_with_json(
             bevy_reflect::ReflectMut::Enum(_) => bail!("Cannot patch enum with array"),
             bevy_reflect::ReflectMut::Value(_) => bail!("Cannot patch primitive value with array"),
         },
+        // Entities are passed from JS as plain objects
+        JsonValueOrReflect::Object(map) if value.is::<Entity>() => {
+            let entity = map
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = value
+                        .into_primitive_value()
+                        .ok_or_else(|| format_err!("Cannot patch entity with `{key}` field"))?;
+                    Ok((key, value))
+                })
+                .collect::<anyhow::Result<serde_json::Map<_, _>>>()?;
+            let entity = serde_json::from_value::<JsEntity>(entity.into())?;
+            *value.downcast_mut::<Entity>().unwrap() = entity.into();
+        }
         JsonValueOrReflect::Object(map) => match value.reflect_mut() {
             bevy_reflect::ReflectMut::Struct(target) => {
                 for (key, value) in map {
@@ -318,6 +332,11 @@ pub fn ecs_value_ref_get(
             {
                 let value = value_ref.get(world)?;
 
+                // Entities are passed to JS as plain values instead of value refs
+                if let Some(entity) = value.downcast_ref::<Entity>() {
+                    return Ok(serde_json::to_value(JsEntity::from(*entity))?);
+                }
+
                 let value = try_downcast_leaf_get!(value for
                     u8, u16, u32, u64, u128, usize,
                     i8, i16, i32, i64, i128, isize,
@@ -367,6 +386,12 @@ pub fn ecs_value_ref_set(
         let new_value = new_value.clone();
         let mut reflect = value_ref.get_mut(world)?;
 
+        // Entities are passed from JS as plain values
+        if let Some(entity) = reflect.downcast_mut::<Entity>() {
+            *entity = serde_json::from_value::<JsEntity>(new_value)?.into();
+            return Ok(serde_json::Value::Null);
+        }
+
         // Try to store a primitive in the value
         let downcast_to_primitive = try_downcast_leaf_set!(reflect <- new_value for
             u8, u16, u32, u64, u128, usize,
@@ -583,6 +608,12 @@ pub fn ecs_value_ref_call(
                 .iter()
                 .zip(method.signature.iter().skip(1))
                 .map(|(arg, &(pass_mode, type_id))| {
+                    // Entities are passed as plain `{ id, generation }` objects
+                    if type_id == TypeId::of::<Entity>() {
+                        let entity: JsEntity = serde_json::from_value(arg.clone())?;
+                        return Ok(ReflectArgIntermediate::Entity(entity.into(), pass_mode));
+                    }
+
                     // Try to cast the arg as a primitive
                     let downcast_primitive = match type_id {
                         type_id if type_id == TypeId::of::<f32>() => {
@@ -692,18 +723,167 @@ pub fn ecs_value_ref_eq(
     ))
 }
 
+/// The value refs that scripts retained with `Value.retain()`, which are kept at the end of the
+/// frame until they are released, or until the script or the instance that retained them is
+/// removed
+///
+/// The value refs are keyed by the script that retained them, and the entity of its instance if
+/// they were retained by an instance, so that scripts can't release each other's value refs and
+/// the value refs of a script module can be released when it is reloaded.
+#[derive(Default, Deref, DerefMut)]
+struct RetainedValueRefs(
+    HashMap<
+        (Handle<JsScript>, Option<Entity>),
+        HashSet<(JsValueRefKey, Option<ReflectFunctionKey>)>,
+    >,
+);
+
+/// The value refs retained by the previous version of script modules that are being reloaded,
+/// which are only released once the new version of the script has loaded
+#[derive(Default, Deref, DerefMut)]
+struct ReloadingValueRefs(
+    HashMap<Handle<JsScript>, HashSet<(JsValueRefKey, Option<ReflectFunctionKey>)>>,
+);
+
+pub fn ecs_value_ref_retain(
+    context: OpContext,
+    _world: &mut World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    // Parse args
+    let (value_ref,): (JsValueRef,) = serde_json::from_value(args).context("parse args")?;
+
+    let value_refs = context
+        .op_state
+        .entry::<JsValueRefs>()
+        .or_insert_with(default);
+    if !value_refs.contains_key(value_ref.key) {
+        bail!("Value ref doesn't exist");
+    }
+    if let Some(function) = value_ref.function {
+        let reflect_functions = context
+            .op_state
+            .entry::<JsReflectFunctions>()
+            .or_insert_with(default);
+        if !reflect_functions.contains_key(function) {
+            bail!("Reflect function doesn't exist");
+        }
+    }
+
+    let script = &context.script_info;
+    context
+        .op_state
+        .entry::<RetainedValueRefs>()
+        .or_insert_with(default)
+        .entry((script.handle.clone_weak(), script.entity))
+        .or_default()
+        .insert((value_ref.key, value_ref.function));
+
+    Ok(serde_json::Value::Null)
+}
+
+pub fn ecs_value_ref_release(
+    context: OpContext,
+    _world: &mut World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    // Parse args
+    let (value_ref,): (JsValueRef,) = serde_json::from_value(args).context("parse args")?;
+
+    // The value ref stays valid until the end of the frame. Scripts can only release the value
+    // refs that they retained themselves, but the module and the instances of a script share
+    // their values.
+    let retained = context
+        .op_state
+        .entry::<RetainedValueRefs>()
+        .or_insert_with(default);
+    for ((script, _), value_refs) in retained.iter_mut() {
+        if script == &context.script_info.handle {
+            value_refs.remove(&(value_ref.key, value_ref.function));
+        }
+    }
+
+    Ok(serde_json::Value::Null)
+}
+
 pub struct EcsValueRefCleanup;
 
 impl JsRuntimeOp for EcsValueRefCleanup {
     fn frame_end(&self, op_state: &mut type_map::TypeMap, _: &mut World) {
+        let retained = op_state
+            .entry::<RetainedValueRefs>()
+            .or_insert_with(default);
+        let retained_value_refs = retained.values().flatten().copied().collect::<Vec<_>>();
+        let retained_keys = retained_value_refs
+            .iter()
+            .map(|(key, _)| *key)
+            .collect::<HashSet<_>>();
+        let retained_functions = retained_value_refs
+            .iter()
+            .filter_map(|(_, function)| *function)
+            .collect::<HashSet<_>>();
+
         op_state
             .entry::<JsValueRefs>()
             .or_insert_with(default)
-            .clear();
+            .retain(|key, _| retained_keys.contains(&key));
         op_state
             .entry::<JsReflectFunctions>()
             .or_insert_with(default)
-            .clear();
+            .retain(|key, _| retained_functions.contains(&key));
+    }
+
+    fn script_unloaded(&self, op_state: &mut type_map::TypeMap, script: &Handle<JsScript>) {
+        if let Some(retained) = op_state.get_mut::<RetainedValueRefs>() {
+            retained.retain(|(retaining_script, _), _| retaining_script != script);
+        }
+    }
+
+    fn instance_removed(
+        &self,
+        op_state: &mut type_map::TypeMap,
+        script: &Handle<JsScript>,
+        entity: Entity,
+    ) {
+        if let Some(retained) = op_state.get_mut::<RetainedValueRefs>() {
+            retained.remove(&(script.clone_weak(), Some(entity)));
+        }
+    }
+
+    fn script_reloading(&self, op_state: &mut type_map::TypeMap, script: &Handle<JsScript>) {
+        // Instances keep their state when their script is reloaded, so only the value refs
+        // retained by the script module are released, once the new version has loaded
+        let previous = op_state
+            .get_mut::<RetainedValueRefs>()
+            .and_then(|retained| retained.remove(&(script.clone_weak(), None)));
+        if let Some(previous) = previous {
+            op_state
+                .entry::<ReloadingValueRefs>()
+                .or_insert_with(default)
+                .insert(script.clone_weak(), previous);
+        }
+    }
+
+    fn script_reloaded(
+        &self,
+        op_state: &mut type_map::TypeMap,
+        script: &Handle<JsScript>,
+        loaded: bool,
+    ) {
+        let previous = op_state
+            .get_mut::<ReloadingValueRefs>()
+            .and_then(|reloading| reloading.remove(script));
+
+        // The previous version of the script keeps running if the new version failed to load
+        if !loaded {
+            let retained = op_state
+                .entry::<RetainedValueRefs>()
+                .or_insert_with(default);
+            match previous {
+                Some(previous) => retained.insert((script.clone_weak(), None), previous),
+                None => retained.remove(&(script.clone_weak(), None)),
+            };
+        }
     }
 }
 
@@ -1,23 +1,22 @@
 use anyhow::{format_err, Context};
-use bevy::prelude::{default, Entity, ReflectComponent};
+use bevy::{
+    hierarchy::despawn_with_children_recursive,
+    prelude::{default, Entity, ReflectComponent},
+};
+use serde::Deserialize;
 
 use crate::{JsValueRef, JsValueRefs, OpContext};
 
-use super::types::ComponentIdOrBevyType;
+use super::types::{ComponentIdOrBevyType, JsEntity};
+
 pub fn ecs_entity_spawn(
-    context: OpContext,
+    _context: OpContext,
     world: &mut bevy::prelude::World,
     _args: serde_json::Value,
 ) -> anyhow::Result<serde_json::Value> {
-    let value_refs = context
-        .op_state
-        .entry::<JsValueRefs>()
-        .or_insert_with(default);
-
     let entity = world.spawn_empty().id();
-    let value_ref = JsValueRef::new_free(Box::new(entity), value_refs);
 
-    Ok(serde_json::to_value(value_ref)?)
+    Ok(serde_json::to_value(JsEntity::from(entity))?)
 }
 
 pub fn ecs_component_insert(
@@ -26,11 +25,8 @@ pub fn ecs_component_insert(
     args: serde_json::Value,
 ) -> anyhow::Result<serde_json::Value> {
     // Parse args
-    let (entity_value_ref, ty, component_value_ref): (
-        JsValueRef,
-        ComponentIdOrBevyType,
-        JsValueRef,
-    ) = serde_json::from_value(args).context("parse args")?;
+    let (entity, ty, component_value_ref): (JsEntity, ComponentIdOrBevyType, JsValueRef) =
+        serde_json::from_value(args).context("parse args")?;
 
     let registration = ty.registration(world, context.type_registry)?;
 
@@ -40,7 +36,7 @@ pub fn ecs_component_insert(
         .or_insert_with(default);
 
     // Get entity and make sure the entity exists
-    let entity = entity_value_ref.get_downcast_copy::<Entity>(world, value_refs)?;
+    let entity = Entity::from(entity);
     world
         .get_entity(entity)
         .ok_or_else(|| format_err!("Entity does not exist"))?;
@@ -68,3 +64,90 @@ pub fn ecs_component_insert(
 
     Ok(serde_json::Value::Null)
 }
+
+pub fn ecs_entity_despawn(
+    _context: OpContext,
+    world: &mut bevy::prelude::World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    // Parse args
+    let (entity, options): (JsEntity, Option<DespawnOptions>) =
+        serde_json::from_value(args).context("parse args")?;
+    let recursive = options.map_or(false, |options| options.recursive);
+
+    // Get entity and make sure the entity exists
+    let entity = Entity::from(entity);
+    world
+        .get_entity(entity)
+        .ok_or_else(|| format_err!("Entity does not exist"))?;
+
+    if recursive {
+        despawn_with_children_recursive(world, entity);
+    } else {
+        world.despawn(entity);
+    }
+
+    Ok(serde_json::Value::Null)
+}
+
+#[derive(Deserialize)]
+struct DespawnOptions {
+    #[serde(default)]
+    recursive: bool,
+}
+
+pub fn ecs_component_remove(
+    context: OpContext,
+    world: &mut bevy::prelude::World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    // Parse args
+    let (entity, ty): (JsEntity, ComponentIdOrBevyType) =
+        serde_json::from_value(args).context("parse args")?;
+
+    let registration = ty.registration(world, context.type_registry)?;
+
+    // Get entity and make sure the entity exists
+    let entity = Entity::from(entity);
+    world
+        .get_entity(entity)
+        .ok_or_else(|| format_err!("Entity does not exist"))?;
+
+    // Get the ReflectComponent
+    let reflect_component = registration
+        .data::<ReflectComponent>()
+        .ok_or_else(|| format_err!("ReflectComponent not found for component"))?
+        .clone();
+
+    // Remove the component from the entity
+    reflect_component.remove(world, entity);
+
+    Ok(serde_json::Value::Null)
+}
+
+pub fn ecs_component_has(
+    context: OpContext,
+    world: &mut bevy::prelude::World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    // Parse args
+    let (entity, ty): (JsEntity, ComponentIdOrBevyType) =
+        serde_json::from_value(args).context("parse args")?;
+
+    // Get entity and make sure the entity exists
+    let entity = Entity::from(entity);
+    let entity_ref = world
+        .get_entity(entity)
+        .ok_or_else(|| format_err!("Entity does not exist"))?;
+
+    // Components that have never been added to any entity don't have an id yet
+    let has_component = match ty.component_id(world, context.type_registry) {
+        Ok(component_id) => entity_ref.contains_id(component_id),
+        Err(_) => {
+            ty.registration(world, context.type_registry)?;
+            false
+        }
+    };
+
+    Ok(serde_json::to_value(has_component)?)
+}
@@ -0,0 +1,222 @@
+use std::any::Any;
+
+use anyhow::{format_err, Context};
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+use crate::{
+    asset::JsScript,
+    events::{EventSubscriptions, ScriptEventTypes, ScriptEvents},
+    runtime::{JsRuntimeOp, OpContext, OpMap},
+    JsValueRef, JsValueRefs,
+};
+
+pub fn insert_event_ops(ops: &mut OpMap) {
+    ops.insert("events.js", Box::new(EventsJs));
+    ops.insert("events_subscribe", Box::new(events_subscribe));
+    ops.insert("events_take", Box::new(events_take));
+    ops.insert("events_read", Box::new(events_read));
+    ops.insert("events_send", Box::new(events_send));
+    ops.insert("events_cleanup", Box::new(ScriptEventReadersCleanup));
+}
+
+/// Op used to provide the `events` global, and the function dispatching the events to the
+/// handlers of a script
+struct EventsJs;
+impl JsRuntimeOp for EventsJs {
+    fn js(&self) -> Option<&'static str> {
+        Some(
+            r#"
+            ((window) => {
+                // Event handlers, keyed by the id of their subscription
+                const handlers = new Map();
+
+                window.events = {
+                    on(eventType, handler) {
+                        const id = bevyModJsScriptingOpSync("events_subscribe", eventType);
+                        handlers.set(id, handler);
+                    },
+                };
+
+                // Run the handler of a subscription for the events sent during the frame. The
+                // handler gets every event even if it throws, and the first error is rethrown.
+                window.bevyModJsScriptingDispatchEvents = (id) => {
+                    const handler = handlers.get(id);
+                    if (!handler) {
+                        return;
+                    }
+
+                    const events = bevyModJsScriptingOpSync("events_take", id);
+                    const errors = [];
+                    for (const event of events) {
+                        try {
+                            handler(Value.wrapValueRef(event));
+                        } catch (e) {
+                            errors.push(e);
+                        }
+                    }
+                    if (errors.length > 0) {
+                        throw errors[0];
+                    }
+                };
+
+                // Drop the handlers of subscriptions that have been removed
+                window.bevyModJsScriptingRemoveEventHandlers = (ids) => {
+                    for (const id of ids) {
+                        handlers.delete(id);
+                    }
+                };
+            })(globalThis);
+            "#,
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct EventType {
+    #[serde(rename = "typeName")]
+    type_name: String,
+}
+
+/// Subscribes the current script to an event type, returning the id of the subscription
+fn events_subscribe(
+    context: OpContext,
+    world: &mut World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (event_type,): (EventType,) = serde_json::from_value(args).context("parse args")?;
+
+    world
+        .resource::<ScriptEventTypes>()
+        .get_fns(&event_type.type_name)?;
+
+    let id = world.resource_mut::<EventSubscriptions>().subscribe(
+        &context.script_info.handle,
+        context.script_info.entity,
+        event_type.type_name,
+    );
+
+    Ok(serde_json::to_value(id)?)
+}
+
+/// Returns the events for a subscription of the current script
+fn events_take(
+    context: OpContext,
+    world: &mut World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (id,): (u32,) = serde_json::from_value(args).context("parse args")?;
+
+    let subscription = world
+        .resource::<EventSubscriptions>()
+        .get(id)
+        .filter(|subscription| subscription.script == context.script_info.handle)
+        .ok_or_else(|| format_err!("Event subscription doesn't exist"))?;
+    let events = world
+        .resource::<ScriptEvents>()
+        .get(&subscription.type_name)
+        .into_iter()
+        .flatten();
+
+    let value_refs = context
+        .op_state
+        .entry::<JsValueRefs>()
+        .or_insert_with(default);
+    let events = events
+        .map(|event| JsValueRef::new_free(event.clone_value(), value_refs))
+        .collect::<Vec<_>>();
+
+    Ok(serde_json::to_value(events)?)
+}
+
+/// The cursors of the scripts reading events with `world.events()`, keyed by the script, the
+/// entity of its instance if an instance is reading the events, and the type name of the events
+#[derive(Default, Deref, DerefMut)]
+struct ScriptEventReaders(
+    HashMap<(Handle<JsScript>, Option<Entity>, String), Option<Box<dyn Any>>>,
+);
+
+/// Op dropping the event readers of the scripts that are unloaded and of the removed instances
+struct ScriptEventReadersCleanup;
+impl JsRuntimeOp for ScriptEventReadersCleanup {
+    fn script_unloaded(&self, op_state: &mut type_map::TypeMap, script: &Handle<JsScript>) {
+        if let Some(readers) = op_state.get_mut::<ScriptEventReaders>() {
+            readers.retain(|(reader_script, _, _), _| reader_script != script);
+        }
+    }
+
+    fn instance_removed(
+        &self,
+        op_state: &mut type_map::TypeMap,
+        script: &Handle<JsScript>,
+        entity: Entity,
+    ) {
+        if let Some(readers) = op_state.get_mut::<ScriptEventReaders>() {
+            readers.retain(|(reader_script, reader_entity, _), _| {
+                reader_script != script || *reader_entity != Some(entity)
+            });
+        }
+    }
+}
+
+/// Returns the events sent since the last time that the current script read events of this type
+fn events_read(
+    context: OpContext,
+    world: &mut World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (event_type,): (EventType,) = serde_json::from_value(args).context("parse args")?;
+    let fns = world
+        .resource::<ScriptEventTypes>()
+        .get_fns(&event_type.type_name)?;
+
+    let reader = context
+        .op_state
+        .entry::<ScriptEventReaders>()
+        .or_insert_with(default)
+        .entry((
+            context.script_info.handle.clone_weak(),
+            context.script_info.entity,
+            event_type.type_name,
+        ))
+        .or_default();
+    let events = (fns.read)(world, reader);
+
+    let value_refs = context
+        .op_state
+        .entry::<JsValueRefs>()
+        .or_insert_with(default);
+    let events = events
+        .into_iter()
+        .map(|event| JsValueRef::new_free(event, value_refs))
+        .collect::<Vec<_>>();
+
+    Ok(serde_json::to_value(events)?)
+}
+
+/// Sends an event to the Rust systems
+fn events_send(
+    context: OpContext,
+    world: &mut World,
+    args: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let (event_type, value_ref): (EventType, JsValueRef) =
+        serde_json::from_value(args).context("parse args")?;
+    let fns = world
+        .resource::<ScriptEventTypes>()
+        .get_fns(&event_type.type_name)?;
+
+    let value_refs = context
+        .op_state
+        .entry::<JsValueRefs>()
+        .or_insert_with(default);
+    let value = value_refs
+        .get(value_ref.key)
+        .ok_or_else(|| format_err!("Value ref doesn't exist"))?
+        .get(world)?
+        .clone_value();
+
+    (fns.send)(world, &*value)?;
+
+    Ok(serde_json::Value::Null)
+}
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use bevy::prelude::*;
@@ -8,14 +8,22 @@ use type_map::TypeMap;
 use wasm_bindgen::{prelude::*, JsCast};
 use wasm_mutex::{Mutex, MutexRef};
 
-use super::{get_ops, JsRuntimeApi, JsRuntimeConfig, OpNames, Ops};
+use super::{
+    get_ops, FnReceiver, JsRuntimeApi, JsRuntimeConfig, JsScriptError, OpNames, Ops,
+    ScriptRunTicks, ScriptSourceMaps,
+};
 use crate::asset::JsScript;
 use crate::runtime::{OpContext, ScriptInfo};
+use crate::transpile::module_key;
 
 /// Panic message when a mutex lock fails
 const LOCK_SHOULD_NOT_FAIL: &str =
     "Mutex lock should not fail because there should be no concurrent access";
 
+/// What the `Function` constructor puts in front of the body of the function evaluating a script,
+/// which counts towards the line numbers in stack traces.
+const FUNCTION_HEADER: &str = "function anonymous(\n) {\n";
+
 #[wasm_bindgen]
 struct BevyModJsScripting {
     state: Rc<Mutex<JsRuntimeState>>,
@@ -81,11 +89,16 @@ extern "C" {
 
 pub struct JsRuntime {
     scripts: Mutex<HashMap<Handle<JsScript>, ScriptData>>,
+    /// The instances of the scripts attached to entities
+    instances: Mutex<HashMap<(Handle<JsScript>, Entity), JsValue>>,
     state: Rc<Mutex<JsRuntimeState>>,
+    source_maps: Mutex<ScriptSourceMaps>,
+    run_ticks: Mutex<ScriptRunTicks>,
 }
 
 struct ScriptData {
     path: PathBuf,
+    /// The exports object of the script's module
     output: wasm_bindgen::JsValue,
 }
 
@@ -113,6 +126,8 @@ impl FromWorld for JsRuntime {
             script_info: ScriptInfo {
                 path: default(),
                 handle: default(),
+                entity: None,
+                last_run_tick: 0,
             },
             world: default(),
         }));
@@ -128,53 +143,157 @@ impl FromWorld for JsRuntime {
 
         Self {
             scripts: Default::default(),
+            instances: Default::default(),
             state,
+            source_maps: Mutex::new(ScriptSourceMaps::new(
+                FUNCTION_HEADER.matches('\n').count() as u32 + super::module_wrapper_line_offset(),
+            )),
+            run_ticks: default(),
         }
     }
 }
 
 impl JsRuntimeApi for JsRuntime {
-    fn load_script(&self, handle: &Handle<JsScript>, script: &JsScript, _reload: bool) {
-        // Set script info
-        {
-            let mut state = self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
-            state.script_info = ScriptInfo {
-                path: script.path.clone(),
-                handle: handle.clone_weak(),
-            };
+    fn load_script(
+        &self,
+        handle: &Handle<JsScript>,
+        reload: bool,
+        world: &mut World,
+    ) -> Result<(), JsScriptError> {
+        let already_loaded = self
+            .scripts
+            .try_lock()
+            .expect(LOCK_SHOULD_NOT_FAIL)
+            .contains_key(handle);
+
+        // Skip if already loaded and we aren't intentionally reloading
+        if already_loaded && !reload {
+            return Ok(());
         }
 
+        let script = world
+            .resource::<Assets<JsScript>>()
+            .get(handle)
+            .expect("Script asset should be loaded");
+        let path = script.path.clone();
+
+        // Register the source map so that errors can be mapped to the original source
+        let mut source_maps = self.source_maps.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+        let previous_source_map = source_maps.insert(script);
+
+        // Name the code after the script with a `sourceURL` comment so that it shows up in stack
+        // traces.
         let function = js_sys::Function::new_no_args(&format!(
-            r#"return ((window) => {{
-                {code}
-            }})(globalThis);"#,
-            code = script.source
+            "return {};\n//# sourceURL={}",
+            super::module_wrapper_code(script),
+            module_key(&path),
         ));
 
-        let output = match function.call0(&JsValue::UNDEFINED) {
-            Ok(output) => output,
-            Err(e) => {
-                error!(?script.path, "Error executing script: {:?}", e);
-                return;
+        // Set script info and give the script access to the world
+        {
+            let JsRuntimeState {
+                op_state,
+                ops,
+                script_info,
+                world: state_world,
+                ..
+            } = &mut *self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+            if already_loaded {
+                for op in ops {
+                    op.script_reloading(op_state, handle);
+                }
             }
-        };
+            *script_info = ScriptInfo {
+                path: path.clone(),
+                handle: handle.clone_weak(),
+                entity: None,
+                last_run_tick: 0,
+            };
+            std::mem::swap(state_world, world);
+        }
+
+        let result = function.call0(&JsValue::UNDEFINED);
 
-        // Clear script info
+        // Clear script info and take the world back
         {
             let mut state = self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
             state.script_info = ScriptInfo {
                 path: default(),
                 handle: default(),
+                entity: None,
+                last_run_tick: 0,
             };
+            std::mem::swap(&mut state.world, world);
+
+            if already_loaded {
+                let JsRuntimeState { op_state, ops, .. } = &mut *state;
+                for op in ops {
+                    op.script_reloaded(op_state, handle, result.is_ok());
+                }
+            }
         }
 
-        self.scripts.try_lock().expect(LOCK_SHOULD_NOT_FAIL).insert(
-            handle.clone_weak(),
-            ScriptData {
-                path: script.path.clone(),
-                output,
-            },
-        );
+        // The script data is only replaced once the new version of the script has been evaluated
+        // successfully, so a failed reload keeps the previous version running.
+        let output = result.map_err(|e| {
+            let error = js_script_error(&e, handle, &path, None, &source_maps);
+            source_maps.restore(&path, previous_source_map);
+            error
+        })?;
+
+        // Make the instances of the script use the new version of the script
+        let instances = self.instances.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+        for ((instance_handle, _), instance) in instances.iter() {
+            if instance_handle == handle {
+                let args = js_sys::Array::of2(instance, &output);
+                if let Err(e) = call_global_fn("bevyModJsScriptingUpdateInstance", &args) {
+                    error!(?path, "Error updating script instance: {e:?}");
+                }
+            }
+        }
+
+        self.scripts
+            .try_lock()
+            .expect(LOCK_SHOULD_NOT_FAIL)
+            .insert(handle.clone_weak(), ScriptData { path, output });
+
+        Ok(())
+    }
+
+    fn unload_script(&self, handle: &Handle<JsScript>) {
+        let script = self
+            .scripts
+            .try_lock()
+            .expect(LOCK_SHOULD_NOT_FAIL)
+            .remove(handle);
+
+        if let Some(script) = script {
+            self.source_maps
+                .try_lock()
+                .expect(LOCK_SHOULD_NOT_FAIL)
+                .remove(&script.path);
+            self.instances
+                .try_lock()
+                .expect(LOCK_SHOULD_NOT_FAIL)
+                .retain(|(instance_handle, _), _| instance_handle != handle);
+            self.run_ticks
+                .try_lock()
+                .expect(LOCK_SHOULD_NOT_FAIL)
+                .remove_script(handle);
+
+            let code = super::unregister_module_code(&script.path);
+            if let Err(e) = js_sys::eval(&code) {
+                error!(?script.path, "Error unregistering script module: {e:?}");
+            }
+
+            let JsRuntimeState { op_state, ops, .. } =
+                &mut *self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+            for op in ops {
+                op.script_unloaded(op_state, handle);
+            }
+
+            debug!(?script.path, "Unloaded script");
+        }
     }
 
     fn has_loaded(&self, handle: &Handle<JsScript>) -> bool {
@@ -184,7 +303,176 @@ impl JsRuntimeApi for JsRuntime {
             .contains_key(handle)
     }
 
-    fn run_script(&self, handle: &Handle<JsScript>, fn_name_str: &str, world: &mut World) {
+    fn run_script(
+        &self,
+        handle: &Handle<JsScript>,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<(), JsScriptError> {
+        self.call_fn(
+            handle,
+            FnReceiver::Exports,
+            fn_name,
+            args,
+            world,
+            |_| Ok(()),
+        )
+        .map(drop)
+    }
+
+    fn create_instance(&self, handle: &Handle<JsScript>, entity: Entity) {
+        let scripts = self.scripts.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+        let script = if let Some(script) = scripts.get(handle) {
+            script
+        } else {
+            return;
+        };
+
+        let args = js_sys::Array::of3(
+            &script.output,
+            &JsValue::from(entity.index()),
+            &JsValue::from(entity.generation()),
+        );
+        match call_global_fn("bevyModJsScriptingCreateInstance", &args) {
+            Ok(instance) => {
+                self.instances
+                    .try_lock()
+                    .expect(LOCK_SHOULD_NOT_FAIL)
+                    .insert((handle.clone_weak(), entity), instance);
+            }
+            Err(e) => error!(?script.path, ?entity, "Could not create script instance: {e:?}"),
+        }
+    }
+
+    fn remove_instance(&self, handle: &Handle<JsScript>, entity: Entity) {
+        self.instances
+            .try_lock()
+            .expect(LOCK_SHOULD_NOT_FAIL)
+            .remove(&(handle.clone_weak(), entity));
+        self.run_ticks
+            .try_lock()
+            .expect(LOCK_SHOULD_NOT_FAIL)
+            .remove_instance(handle, entity);
+
+        let JsRuntimeState { op_state, ops, .. } =
+            &mut *self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+        for op in ops {
+            op.instance_removed(op_state, handle, entity);
+        }
+    }
+
+    fn run_instance(
+        &self,
+        handle: &Handle<JsScript>,
+        entity: Entity,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<(), JsScriptError> {
+        self.call_fn(
+            handle,
+            FnReceiver::Instance(entity),
+            fn_name,
+            args,
+            world,
+            |_| Ok(()),
+        )
+        .map(drop)
+    }
+
+    fn call_script_fn(
+        &self,
+        handle: &Handle<JsScript>,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<Option<serde_json::Value>, JsScriptError> {
+        self.call_fn(handle, FnReceiver::Exports, fn_name, args, world, |value| {
+            serde_wasm_bindgen::from_value(value)
+                .map_err(|e| format!("Could not convert return value: {e}"))
+        })
+    }
+
+    fn run_global_fn(
+        &self,
+        handle: &Handle<JsScript>,
+        entity: Option<Entity>,
+        fn_name: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+    ) -> Result<(), JsScriptError> {
+        self.call_fn(
+            handle,
+            FnReceiver::Global(entity),
+            fn_name,
+            args,
+            world,
+            |_| Ok(()),
+        )
+        .map(drop)
+    }
+
+    fn snapshot_exports(&self, handle: &Handle<JsScript>) -> serde_json::Value {
+        let scripts = self.scripts.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+        let script = if let Some(script) = scripts.get(handle) {
+            script
+        } else {
+            return serde_json::Value::Null;
+        };
+
+        // Serialize the exports with `JSON.stringify()`, which skips the functions
+        js_sys::JSON::stringify(&script.output)
+            .ok()
+            .and_then(|json| serde_json::from_str(&String::from(json)).ok())
+            .unwrap_or_default()
+    }
+
+    fn frame_start(&self, world: &mut World) {
+        let JsRuntimeState { op_state, ops, .. } =
+            &mut *self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+
+        for op in ops {
+            op.frame_start(op_state, world);
+        }
+    }
+
+    fn frame_end(&self, world: &mut World) {
+        let JsRuntimeState { op_state, ops, .. } =
+            &mut *self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+
+        for op in ops {
+            op.frame_end(op_state, world);
+        }
+
+        self.run_ticks
+            .try_lock()
+            .expect(LOCK_SHOULD_NOT_FAIL)
+            .check_change_ticks(world);
+    }
+}
+
+impl JsRuntime {
+    /// Call a function exported by a script, converting its return value with `convert`
+    ///
+    /// If `entity` is set, the function is called on the instance of the script for that entity.
+    /// Returns `None` if the script or the instance isn't loaded, or if the script doesn't export
+    /// the function.
+    fn call_fn<T>(
+        &self,
+        handle: &Handle<JsScript>,
+        fn_receiver: FnReceiver,
+        fn_name_str: &str,
+        args: &[serde_json::Value],
+        world: &mut World,
+        convert: impl FnOnce(JsValue) -> Result<T, String>,
+    ) -> Result<Option<T>, JsScriptError> {
+        let last_run_tick = self
+            .run_ticks
+            .try_lock()
+            .expect(LOCK_SHOULD_NOT_FAIL)
+            .last_run_tick(handle, fn_receiver.entity(), world);
+
         {
             let mut state = self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
             std::mem::swap(&mut state.world, world);
@@ -192,79 +480,187 @@ impl JsRuntimeApi for JsRuntime {
 
         let try_run = || {
             let scripts = self.scripts.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
-            let script = scripts
-                .get(handle)
-                .ok_or_else(|| anyhow::format_err!("Script not loaded yet"))?;
+            let script = if let Some(script) = scripts.get(handle) {
+                script
+            } else {
+                return Ok(None);
+            };
             let output = &script.output;
 
+            // Get the instance of the script for the entity
+            let instance = match fn_receiver {
+                FnReceiver::Instance(entity) => {
+                    let instances = self.instances.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+                    match instances.get(&(handle.clone_weak(), entity)) {
+                        Some(instance) => Some(instance.clone()),
+                        None => return Ok(None),
+                    }
+                }
+                FnReceiver::Exports | FnReceiver::Global(_) => None,
+            };
+
             {
                 let mut state = self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
                 state.script_info = ScriptInfo {
                     path: script.path.clone(),
                     handle: handle.clone_weak(),
+                    entity: fn_receiver.entity(),
+                    last_run_tick,
                 };
             }
 
-            let output: &js_sys::Object = output.dyn_ref().ok_or_else(|| {
-                anyhow::format_err!("Script must have a default export that returns an object")
-            })?;
-
             let fn_name_str = wasm_bindgen::intern(fn_name_str);
-            let fn_name = wasm_bindgen::JsValue::from_str(fn_name_str);
 
-            if let Ok(script_fn) = js_sys::Reflect::get(output, &fn_name) {
-                // If a handler isn't specified for this stage, just skip this script
+            // Functions stored in an exported object are named `object.key`
+            let (export_name, key) = match fn_name_str.split_once('.') {
+                Some((export_name, key)) => (export_name, Some(key)),
+                None => (fn_name_str, None),
+            };
+            let fn_name = wasm_bindgen::JsValue::from_str(export_name);
+
+            // Look for the function in the named exports of the module first, and fall back to
+            // the default export object, which may also hold the stage functions. Global functions
+            // are looked up on the global object instead.
+            let default_export = js_sys::Reflect::get(output, &JsValue::from_str("default"))
+                .unwrap_or(JsValue::UNDEFINED);
+            let (global, undefined) = (JsValue::from(js_sys::global()), JsValue::UNDEFINED);
+            let receivers = match fn_receiver {
+                FnReceiver::Global(_) => [&global, &undefined],
+                FnReceiver::Exports | FnReceiver::Instance(_) => [output, &default_export],
+            };
+            for receiver in receivers {
+                // Only objects can hold stage functions
+                if !receiver.is_object() && !receiver.is_function() {
+                    continue;
+                }
+
+                let mut receiver = receiver.clone();
+                let mut script_fn =
+                    js_sys::Reflect::get(&receiver, &fn_name).unwrap_or(JsValue::UNDEFINED);
+                if let Some(key) = key {
+                    if !script_fn.is_object() {
+                        continue;
+                    }
+                    let value = js_sys::Reflect::get(&script_fn, &JsValue::from_str(key))
+                        .unwrap_or(JsValue::UNDEFINED);
+                    receiver = std::mem::replace(&mut script_fn, value);
+                }
+
+                // If a handler isn't specified on this object, keep looking
                 if script_fn.is_undefined() {
-                    return Ok(());
+                    continue;
                 }
 
                 match script_fn.dyn_ref::<js_sys::Function>() {
                     Some(script_fn) => {
-                        script_fn.call0(output).map_err(|e| {
-                            anyhow::format_err!("Error running script {fn_name_str} handler: {e:?}")
+                        // Convert the arguments to JavaScript values
+                        let serializer = &serde_wasm_bindgen::Serializer::json_compatible();
+                        let js_args = args
+                            .iter()
+                            .map(|arg| arg.serialize(serializer))
+                            .collect::<Result<js_sys::Array, _>>()
+                            .map_err(|e| JsScriptError {
+                                handle: handle.clone_weak(),
+                                path: script.path.clone(),
+                                stage_fn: Some(fn_name_str.to_owned()),
+                                message: format!("Could not convert arguments: {e}"),
+                                stack: None,
+                            })?;
+
+                        // Functions run on an instance are called with `this` set to the instance
+                        let receiver = instance.as_ref().unwrap_or(&receiver);
+
+                        let value = script_fn.apply(receiver, &js_args).map_err(|e| {
+                            let source_maps =
+                                self.source_maps.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+                            js_script_error(
+                                &e,
+                                handle,
+                                &script.path,
+                                Some(fn_name_str),
+                                &source_maps,
+                            )
                         })?;
+
+                        return convert(value).map(Some).map_err(|message| JsScriptError {
+                            handle: handle.clone_weak(),
+                            path: script.path.clone(),
+                            stage_fn: Some(fn_name_str.to_owned()),
+                            message,
+                            stack: None,
+                        });
                     }
                     None => {
                         warn!(
-                            "Script exported object with {fn_name_str} field, but it was not a \
-                            function. Ignoring."
+                            "Script exported {fn_name_str}, but it was not a function. Ignoring."
                         );
                     }
                 }
+
+                break;
             }
 
-            Ok::<_, anyhow::Error>(())
+            Ok(None)
         };
 
-        if let Err(e) = try_run() {
-            // TODO: add script path to error
-            error!("Error running script: {}", e);
-        }
+        let result = try_run();
 
         let mut state = self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
         state.script_info = ScriptInfo {
             path: default(),
             handle: default(),
+            entity: None,
+            last_run_tick: 0,
         };
         std::mem::swap(&mut state.world, world);
-    }
-
-    fn frame_start(&self, world: &mut World) {
-        let JsRuntimeState { op_state, ops, .. } =
-            &mut *self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
-
-        for op in ops {
-            op.frame_start(op_state, world);
+        drop(state);
+
+        // Only the calls of functions that the script exports count as runs of the script, and
+        // not the global functions run on its behalf
+        if !matches!(result, Ok(None)) && !matches!(fn_receiver, FnReceiver::Global(_)) {
+            self.run_ticks
+                .try_lock()
+                .expect(LOCK_SHOULD_NOT_FAIL)
+                .end_call(handle, fn_receiver.entity(), world);
         }
+
+        result
     }
+}
 
-    fn frame_end(&self, world: &mut World) {
-        let JsRuntimeState { op_state, ops, .. } =
-            &mut *self.state.try_lock().expect(LOCK_SHOULD_NOT_FAIL);
+/// Calls one of the global functions set up by the runtime's JavaScript.
+fn call_global_fn(name: &str, args: &js_sys::Array) -> Result<JsValue, JsValue> {
+    let function = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str(name))?;
+    function
+        .dyn_into::<js_sys::Function>()?
+        .apply(&JsValue::UNDEFINED, args)
+}
 
-        for op in ops {
-            op.frame_start(op_state, world);
-        }
+/// Creates a [`JsScriptError`] from a JavaScript exception, mapping the locations in its stack
+/// trace to the original script sources.
+fn js_script_error(
+    error: &JsValue,
+    handle: &Handle<JsScript>,
+    path: &Path,
+    stage_fn: Option<&str>,
+    source_maps: &ScriptSourceMaps,
+) -> JsScriptError {
+    let message = js_sys::Reflect::get(error, &JsValue::from_str("message"))
+        .ok()
+        .and_then(|message| message.as_string())
+        .or_else(|| error.as_string())
+        .unwrap_or_else(|| format!("{error:?}"));
+    let stack = js_sys::Reflect::get(error, &JsValue::from_str("stack"))
+        .ok()
+        .and_then(|stack| stack.as_string())
+        .map(|stack| source_maps.map_stack(&stack));
+
+    JsScriptError {
+        handle: handle.clone_weak(),
+        path: path.to_owned(),
+        stage_fn: stage_fn.map(ToOwned::to_owned),
+        message,
+        stack,
     }
 }
 
@@ -1,6 +1,7 @@
 use std::{
+    collections::{HashMap, HashSet},
     io::{Cursor, Write},
-    path::Path,
+    path::{Component, Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
@@ -9,15 +10,19 @@ use swc_common::{
     comments::SingleThreadedComments,
     errors::{EmitterWriter, Handler},
     sync::Lrc,
-    BytePos, Globals, Mark, SourceMap, Span, GLOBALS,
+    BytePos, Globals, Mark, SourceMap, Span, SyntaxContext, DUMMY_SP, GLOBALS,
 };
 use swc_ecma_codegen::{text_writer::JsWriter, Emitter};
 use swc_ecma_parser::{lexer::Lexer, Parser, StringInput, Syntax, TsConfig};
 use swc_ecma_transforms_base::{fixer::fixer, hygiene::hygiene, resolver};
 use swc_ecma_transforms_typescript::strip;
 use swc_ecma_visit::{
-    swc_ecma_ast::{ModuleDecl, ModuleItem, ReturnStmt, Stmt},
-    FoldWith,
+    swc_ecma_ast::{
+        ClassDecl, ClassExpr, Decl, DefaultDecl, ExportDecl, ExportSpecifier, Expr, ExprStmt,
+        FnDecl, FnExpr, Ident, ImportSpecifier, KeyValueProp, Module, ModuleDecl, ModuleExportName,
+        ModuleItem, ObjectPatProp, Pat, Prop, PropName, Stmt,
+    },
+    FoldWith, VisitMut, VisitMutWith,
 };
 
 struct SharedWriter<W>(Arc<Mutex<W>>);
@@ -32,10 +37,23 @@ impl<W: Write> Write for SharedWriter<W> {
     }
 }
 
-pub fn transpile(path: &Path, js: &str) -> Result<String, anyhow::Error> {
+/// The result of transpiling a script with [`transpile`].
+pub struct TranspiledScript {
+    /// The JavaScript body of the module.
+    ///
+    /// It expects to be evaluated inside of a function that provides the module's `exports`
+    /// object.
+    pub code: String,
+    /// The asset paths of the scripts imported by this script.
+    pub dependencies: Vec<PathBuf>,
+    /// Maps locations in [`code`][Self::code] back to the original source of the script.
+    pub source_map: sourcemap::SourceMap,
+}
+
+pub fn transpile(path: &Path, js: &str) -> Result<TranspiledScript, anyhow::Error> {
     let cm: Lrc<SourceMap> = Default::default();
 
-    cm.new_source_file(path.to_owned().into(), js.to_owned());
+    let source_file = cm.new_source_file(path.to_owned().into(), js.to_owned());
 
     let error_output = Arc::new(Mutex::new(Cursor::new(Vec::new())));
     let emitter = EmitterWriter::new(
@@ -53,7 +71,7 @@ pub fn transpile(path: &Path, js: &str) -> Result<String, anyhow::Error> {
             ..Default::default()
         }),
         Default::default(),
-        StringInput::new(js, BytePos(0), BytePos(0)),
+        StringInput::from(&*source_file),
         Some(&comments),
     );
 
@@ -80,45 +98,23 @@ pub fn transpile(path: &Path, js: &str) -> Result<String, anyhow::Error> {
         }
     };
 
-    // Rewrite module import/exports
-
     let globals = Globals::default();
     let ts = GLOBALS.set(&globals, || {
         let unresolved_mark = Mark::new();
         let top_level_mark = Mark::new();
 
-        let mut body = Vec::new();
-        std::mem::swap(&mut body, &mut module.body);
-        for item in body {
-            if let ModuleItem::ModuleDecl(decl) = item {
-                match decl {
-                    ModuleDecl::ExportDefaultExpr(expr) => {
-                        module.body.push(ModuleItem::Stmt(Stmt::Return(ReturnStmt {
-                            span: Span::dummy_with_cmt(),
-                            arg: Some(expr.expr),
-                        })))
-                    }
-                    ModuleDecl::ExportNamed(_)
-                    | ModuleDecl::ExportDefaultDecl(_)
-                    | ModuleDecl::ExportAll(_)
-                    | ModuleDecl::TsNamespaceExport(_)
-                    | ModuleDecl::ExportDecl(_) => {
-                        anyhow::bail!("Only default expression exports are supported currently")
-                    }
-                    ModuleDecl::TsImportEquals(_)
-                    | ModuleDecl::TsExportAssignment(_)
-                    | ModuleDecl::Import(_) => anyhow::bail!("Imports are not yet supported"),
-                }
-            } else {
-                module.body.push(item);
-            }
-        }
+        // Rewrite module import/exports
+        let (dependencies, mut imports) = rewrite_module_decls(path, &mut module)?;
 
         // Conduct identifier scope analysis
         let module = module.fold_with(&mut resolver(unresolved_mark, top_level_mark, true));
 
         // Remove typescript types
-        let module = module.fold_with(&mut strip(top_level_mark));
+        let mut module = module.fold_with(&mut strip(top_level_mark));
+
+        // Look up the imported bindings in the module registry
+        imports.unresolved = SyntaxContext::empty().apply_mark(unresolved_mark);
+        module.visit_mut_with(&mut imports);
 
         // Fix up any identifiers with the same name, but different contexts
         let module = module.fold_with(&mut hygiene());
@@ -127,6 +123,7 @@ pub fn transpile(path: &Path, js: &str) -> Result<String, anyhow::Error> {
         let module = module.fold_with(&mut fixer(Some(&comments)));
 
         let mut buf = vec![];
+        let mut source_map_entries = vec![];
         {
             let mut emitter = Emitter {
                 cfg: swc_ecma_codegen::Config {
@@ -135,14 +132,570 @@ pub fn transpile(path: &Path, js: &str) -> Result<String, anyhow::Error> {
                 },
                 cm: cm.clone(),
                 comments: Some(&comments),
-                wr: JsWriter::new(cm.clone(), "\n", &mut buf, None),
+                wr: JsWriter::new(cm.clone(), "\n", &mut buf, Some(&mut source_map_entries)),
             };
 
             emitter.emit_module(&module).unwrap();
         }
 
-        Ok(String::from_utf8(buf))
-    })??;
+        Ok::<_, anyhow::Error>(TranspiledScript {
+            code: String::from_utf8(buf)?,
+            source_map: cm.build_source_map(&mut source_map_entries),
+            dependencies,
+        })
+    })?;
 
     Ok(ts)
 }
+
+/// Returns the key that the module of the script at the given asset path is registered under in
+/// the JS runtime.
+pub fn module_key(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Resolves an import specifier to the asset path of the imported script.
+///
+/// Only relative specifiers are supported. If the specifier has no file extension, the extension
+/// of the importing script is used.
+pub fn resolve_import(importer: &Path, specifier: &str) -> anyhow::Result<PathBuf> {
+    if !(specifier.starts_with("./") || specifier.starts_with("../")) {
+        anyhow::bail!("Only relative imports are supported, but found import from `{specifier}`");
+    }
+
+    let mut resolved = importer.parent().map(Path::to_path_buf).unwrap_or_default();
+    for component in Path::new(specifier).components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    anyhow::bail!("Import from `{specifier}` points outside of the asset folder");
+                }
+            }
+            Component::Normal(part) => resolved.push(part),
+            Component::RootDir | Component::Prefix(_) => {
+                anyhow::bail!("Invalid import from `{specifier}`")
+            }
+        }
+    }
+
+    if resolved.extension().is_none() {
+        if let Some(extension) = importer.extension() {
+            resolved.set_extension(extension);
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Rewrites the import and export declarations of a module into plain statements that use the
+/// module registry of the JS runtime, returning the asset paths of the imported scripts.
+///
+/// Imports are removed, and the [`ImportedBindings`] returned along with the dependencies replace
+/// the references to the imported bindings once the scopes of the module have been resolved.
+/// Exports are defined as getters on the `exports` object provided by the function the module is
+/// evaluated in.
+fn rewrite_module_decls(
+    path: &Path,
+    module: &mut Module,
+) -> anyhow::Result<(Vec<PathBuf>, ImportedBindings)> {
+    let value_names = top_level_value_names(module);
+
+    let mut dependencies = Vec::new();
+    let mut imports = ImportedBindings::default();
+    // List of `(exported name, local name)` pairs
+    let mut exports = Vec::new();
+    // Statements defining the exports re-exported from other modules
+    let mut reexport_items = Vec::new();
+    let mut body = Vec::new();
+
+    for item in std::mem::take(&mut module.body) {
+        let decl = match item {
+            ModuleItem::ModuleDecl(decl) => decl,
+            ModuleItem::Stmt(stmt) => {
+                body.push(ModuleItem::Stmt(stmt));
+                continue;
+            }
+        };
+
+        match decl {
+            ModuleDecl::Import(import) => {
+                if import.type_only {
+                    continue;
+                }
+
+                let dependency = resolve_import(path, &import.src.value)?;
+                let module = serde_json::to_string(&module_key(&dependency))?;
+                let import_expr = format!("bevyModJsScriptingImport({module})");
+
+                for specifier in &import.specifiers {
+                    let (local, lookup) = match specifier {
+                        ImportSpecifier::Named(specifier) => {
+                            if specifier.is_type_only {
+                                continue;
+                            }
+                            let imported = specifier
+                                .imported
+                                .as_ref()
+                                .map(export_name)
+                                .unwrap_or_else(|| specifier.local.sym.to_string());
+                            let lookup =
+                                format!("{import_expr}[{}]", serde_json::to_string(&imported)?);
+                            (&specifier.local, lookup)
+                        }
+                        ImportSpecifier::Default(specifier) => {
+                            (&specifier.local, format!("{import_expr}.default"))
+                        }
+                        ImportSpecifier::Namespace(specifier) => {
+                            (&specifier.local, import_expr.clone())
+                        }
+                    };
+                    imports
+                        .lookups
+                        .insert(local.sym.to_string(), parse_expr_snippet(&lookup)?);
+                }
+
+                dependencies.push(dependency);
+            }
+            ModuleDecl::ExportDecl(export) => {
+                exports.extend(decl_value_names(&export.decl).map(|name| (name.clone(), name)));
+                body.push(ModuleItem::Stmt(Stmt::Decl(export.decl)));
+            }
+            ModuleDecl::ExportNamed(export) if export.src.is_none() => {
+                if export.type_only {
+                    continue;
+                }
+
+                for specifier in &export.specifiers {
+                    match specifier {
+                        ExportSpecifier::Named(specifier) => {
+                            if specifier.is_type_only {
+                                continue;
+                            }
+                            let local = export_name(&specifier.orig);
+                            let exported = specifier
+                                .exported
+                                .as_ref()
+                                .map(export_name)
+                                .unwrap_or_else(|| local.clone());
+                            exports.push((exported, local));
+                        }
+                        ExportSpecifier::Default(_) | ExportSpecifier::Namespace(_) => {
+                            anyhow::bail!("Invalid export specifier")
+                        }
+                    }
+                }
+            }
+            ModuleDecl::ExportNamed(export) => {
+                if export.type_only {
+                    continue;
+                }
+
+                // Re-exports are getters that look up the value on the imported module
+                let src = export.src.as_ref().unwrap();
+                let dependency = resolve_import(path, &src.value)?;
+                let module = serde_json::to_string(&module_key(&dependency))?;
+                let import_expr = format!("bevyModJsScriptingImport({module})");
+
+                for specifier in &export.specifiers {
+                    let (exported, value) = match specifier {
+                        ExportSpecifier::Named(specifier) => {
+                            if specifier.is_type_only {
+                                continue;
+                            }
+                            let orig = export_name(&specifier.orig);
+                            let exported = specifier
+                                .exported
+                                .as_ref()
+                                .map(export_name)
+                                .unwrap_or_else(|| orig.clone());
+                            let value = format!("{import_expr}[{}]", serde_json::to_string(&orig)?);
+                            (exported, value)
+                        }
+                        ExportSpecifier::Namespace(specifier) => {
+                            (export_name(&specifier.name), import_expr.clone())
+                        }
+                        ExportSpecifier::Default(specifier) => (
+                            specifier.exported.sym.to_string(),
+                            format!("{import_expr}.default"),
+                        ),
+                    };
+                    reexport_items.extend(parse_snippet(&format!(
+                        "Object.defineProperty(exports, {}, {{ enumerable: true, get: () => {value} }});",
+                        serde_json::to_string(&exported)?
+                    ))?);
+                }
+
+                dependencies.push(dependency);
+            }
+            ModuleDecl::ExportAll(export) => {
+                let dependency = resolve_import(path, &export.src.value)?;
+                let module = serde_json::to_string(&module_key(&dependency))?;
+
+                // Re-export everything but the default export, without overriding the exports
+                // of this module.
+                reexport_items.extend(parse_snippet(&format!(
+                    r#"Object.keys(bevyModJsScriptingImport({module})).forEach((key) => {{
+                        if (key !== "default" && !(key in exports)) {{
+                            Object.defineProperty(exports, key, {{
+                                enumerable: true,
+                                get: () => bevyModJsScriptingImport({module})[key],
+                            }});
+                        }}
+                    }});"#
+                ))?);
+
+                dependencies.push(dependency);
+            }
+            ModuleDecl::ExportDefaultExpr(export) => {
+                body.extend(default_export_assignment(export.expr)?);
+            }
+            ModuleDecl::ExportDefaultDecl(export) => match export.decl {
+                // Named declarations stay declarations, so that they can be referenced by name
+                DefaultDecl::Fn(FnExpr {
+                    ident: Some(ident),
+                    function,
+                }) => {
+                    exports.push(("default".to_owned(), ident.sym.to_string()));
+                    body.push(ModuleItem::Stmt(Stmt::Decl(Decl::Fn(FnDecl {
+                        ident,
+                        declare: false,
+                        function,
+                    }))));
+                }
+                DefaultDecl::Class(ClassExpr {
+                    ident: Some(ident),
+                    class,
+                }) => {
+                    exports.push(("default".to_owned(), ident.sym.to_string()));
+                    body.push(ModuleItem::Stmt(Stmt::Decl(Decl::Class(ClassDecl {
+                        ident,
+                        declare: false,
+                        class,
+                    }))));
+                }
+                DefaultDecl::Fn(function) => {
+                    body.extend(default_export_assignment(Box::new(Expr::Fn(function)))?);
+                }
+                DefaultDecl::Class(class) => {
+                    body.extend(default_export_assignment(Box::new(Expr::Class(class)))?);
+                }
+                DefaultDecl::TsInterfaceDecl(_) => (),
+            },
+            // Only relevant for type declarations
+            ModuleDecl::TsNamespaceExport(_) => (),
+            ModuleDecl::TsImportEquals(_) | ModuleDecl::TsExportAssignment(_) => {
+                anyhow::bail!("CommonJS style imports and exports are not supported")
+            }
+        }
+    }
+
+    // Define the exports before anything else, so that they are available to modules with
+    // circular imports. The getters keep the exported bindings live.
+    let mut export_items = Vec::new();
+    for (exported, local) in exports {
+        // Names that only refer to types don't exist at runtime
+        if !value_names.contains(&local) {
+            continue;
+        }
+        export_items.extend(parse_snippet(&format!(
+            "Object.defineProperty(exports, {}, {{ enumerable: true, get: () => {local} }});",
+            serde_json::to_string(&exported)?
+        ))?);
+    }
+    export_items.extend(reexport_items);
+    export_items.extend(body);
+    module.body = export_items;
+
+    Ok((dependencies, imports))
+}
+
+/// Replaces the references to the bindings imported from other modules with lookups in the module
+/// registry of the JS runtime.
+///
+/// This makes imports live bindings, like in ES modules, so that modules importing each other can
+/// be evaluated one after the other, as long as they don't use each other's exports while being
+/// evaluated.
+#[derive(Default)]
+struct ImportedBindings {
+    /// Maps the local names of the imported bindings to the expressions looking them up
+    lookups: HashMap<String, Box<Expr>>,
+    /// The syntax context of the identifiers that don't refer to any declaration in the module,
+    /// which the references to the removed imports have after resolving the module's scopes
+    unresolved: SyntaxContext,
+}
+
+impl ImportedBindings {
+    fn lookup(&self, ident: &Ident) -> Option<Box<Expr>> {
+        if ident.span.ctxt != self.unresolved {
+            return None;
+        }
+        self.lookups.get(&*ident.sym).cloned()
+    }
+}
+
+impl VisitMut for ImportedBindings {
+    fn visit_mut_expr(&mut self, expr: &mut Expr) {
+        if let Expr::Ident(ident) = expr {
+            if let Some(lookup) = self.lookup(ident) {
+                *expr = *lookup;
+                return;
+            }
+        }
+        expr.visit_mut_children_with(self);
+    }
+
+    fn visit_mut_prop(&mut self, prop: &mut Prop) {
+        if let Prop::Shorthand(ident) = prop {
+            if let Some(lookup) = self.lookup(ident) {
+                *prop = Prop::KeyValue(KeyValueProp {
+                    key: PropName::Ident(ident.clone()),
+                    value: lookup,
+                });
+                return;
+            }
+        }
+        prop.visit_mut_children_with(self);
+    }
+}
+
+/// Collects the names of all values declared or imported at the top level of a module.
+fn top_level_value_names(module: &Module) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for item in &module.body {
+        match item {
+            ModuleItem::Stmt(Stmt::Decl(decl))
+            | ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(ExportDecl { decl, .. })) => {
+                names.extend(decl_value_names(decl));
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(export)) => match &export.decl {
+                DefaultDecl::Fn(FnExpr {
+                    ident: Some(ident), ..
+                })
+                | DefaultDecl::Class(ClassExpr {
+                    ident: Some(ident), ..
+                }) => {
+                    names.insert(ident.sym.to_string());
+                }
+                _ => (),
+            },
+            ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => {
+                for specifier in &import.specifiers {
+                    let local = match specifier {
+                        ImportSpecifier::Named(specifier) => &specifier.local,
+                        ImportSpecifier::Default(specifier) => &specifier.local,
+                        ImportSpecifier::Namespace(specifier) => &specifier.local,
+                    };
+                    names.insert(local.sym.to_string());
+                }
+            }
+            _ => (),
+        }
+    }
+    names
+}
+
+/// Returns the names of the runtime values introduced by a declaration.
+fn decl_value_names(decl: &Decl) -> impl Iterator<Item = String> {
+    let mut names = Vec::new();
+    match decl {
+        Decl::Class(class) if !class.declare => names.push(class.ident.sym.to_string()),
+        Decl::Fn(function) if !function.declare => names.push(function.ident.sym.to_string()),
+        Decl::Var(var) if !var.declare => {
+            for declarator in &var.decls {
+                pat_names(&declarator.name, &mut names);
+            }
+        }
+        Decl::TsEnum(ts_enum) if !ts_enum.declare => names.push(ts_enum.id.sym.to_string()),
+        _ => (),
+    }
+    names.into_iter()
+}
+
+/// Collects the names of the bindings in a destructuring pattern.
+fn pat_names(pat: &Pat, names: &mut Vec<String>) {
+    match pat {
+        Pat::Ident(ident) => names.push(ident.id.sym.to_string()),
+        Pat::Array(array) => {
+            for elem in array.elems.iter().flatten() {
+                pat_names(elem, names);
+            }
+        }
+        Pat::Object(object) => {
+            for prop in &object.props {
+                match prop {
+                    ObjectPatProp::KeyValue(prop) => pat_names(&prop.value, names),
+                    ObjectPatProp::Assign(prop) => names.push(prop.key.sym.to_string()),
+                    ObjectPatProp::Rest(rest) => pat_names(&rest.arg, names),
+                }
+            }
+        }
+        Pat::Rest(rest) => pat_names(&rest.arg, names),
+        Pat::Assign(assign) => pat_names(&assign.left, names),
+        Pat::Invalid(_) | Pat::Expr(_) => (),
+    }
+}
+
+/// Returns the statement assigning the default export of a module to an expression.
+fn default_export_assignment(value: Box<Expr>) -> anyhow::Result<Vec<ModuleItem>> {
+    let mut assignment = parse_snippet("exports.default = undefined;")?;
+    if let Some(ModuleItem::Stmt(Stmt::Expr(ExprStmt { expr, .. }))) = assignment.first_mut() {
+        if let Expr::Assign(assign) = &mut **expr {
+            assign.right = value;
+        }
+    }
+    Ok(assignment)
+}
+
+fn export_name(name: &ModuleExportName) -> String {
+    match name {
+        ModuleExportName::Ident(ident) => ident.sym.to_string(),
+        ModuleExportName::Str(s) => s.value.to_string(),
+    }
+}
+
+/// Parses a snippet of generated JavaScript consisting of a single expression.
+fn parse_expr_snippet(js: &str) -> anyhow::Result<Box<Expr>> {
+    match parse_snippet(js)?.pop() {
+        Some(ModuleItem::Stmt(Stmt::Expr(ExprStmt { expr, .. }))) => Ok(expr),
+        _ => anyhow::bail!("Generated code `{js}` is not an expression"),
+    }
+}
+
+/// Parses a snippet of generated JavaScript into module items that can be inserted into the
+/// transpiled module.
+fn parse_snippet(js: &str) -> anyhow::Result<Vec<ModuleItem>> {
+    let lexer = Lexer::new(
+        Syntax::Es(Default::default()),
+        Default::default(),
+        StringInput::new(js, BytePos(0), BytePos(0)),
+        None,
+    );
+    let mut parser = Parser::new_from(lexer);
+    let mut module = parser
+        .parse_module()
+        .map_err(|e| anyhow::anyhow!("Failed to parse generated code `{js}`: {:?}", e.kind()))?;
+
+    // The spans of the snippet don't correspond to anything in the original source
+    module.visit_mut_with(&mut DummySpans);
+
+    Ok(module.body)
+}
+
+struct DummySpans;
+impl VisitMut for DummySpans {
+    fn visit_mut_span(&mut self, span: &mut Span) {
+        *span = DUMMY_SP;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_import() {
+        let importer = Path::new("scripts/game/player.ts");
+
+        assert_eq!(
+            resolve_import(importer, "./util.ts").unwrap(),
+            PathBuf::from("scripts/game/util.ts")
+        );
+        assert_eq!(
+            resolve_import(importer, "../util").unwrap(),
+            PathBuf::from("scripts/util.ts")
+        );
+        assert!(resolve_import(importer, "../../../util.ts").is_err());
+        assert!(resolve_import(importer, "util.ts").is_err());
+    }
+
+    #[test]
+    fn test_transpile_imports_and_exports() {
+        let transpiled = transpile(
+            Path::new("scripts/game.ts"),
+            r#"
+            import type { Unused } from "./types";
+            import { double, Vec as V } from "./util";
+            type Local = number;
+            export const value: Local = double(2);
+            export { Local };
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            transpiled.dependencies,
+            vec![PathBuf::from("scripts/util.ts")]
+        );
+        assert!(transpiled
+            .code
+            .contains(r#"bevyModJsScriptingImport("scripts/util.ts")"#));
+        assert!(transpiled
+            .code
+            .contains(r#"Object.defineProperty(exports, "value""#));
+        assert!(!transpiled.code.contains(r#""Local""#));
+    }
+
+    #[test]
+    fn test_transpile_export_forms() {
+        let transpiled = transpile(
+            Path::new("scripts/game.ts"),
+            r#"
+            export function update() {}
+            export default function main() {}
+            export { double as twice } from "./util";
+            export * from "./math";
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            transpiled.dependencies,
+            vec![
+                PathBuf::from("scripts/util.ts"),
+                PathBuf::from("scripts/math.ts")
+            ]
+        );
+        for exported in ["update", "default", "twice"] {
+            assert!(transpiled
+                .code
+                .contains(&format!(r#"Object.defineProperty(exports, "{exported}""#)));
+        }
+        assert!(transpiled.code.contains("function main()"));
+    }
+
+    #[test]
+    fn test_transpile_imports_as_live_bindings() {
+        let transpiled = transpile(
+            Path::new("scripts/a.ts"),
+            r#"
+            import { count, b } from "./b";
+            import * as c from "./c";
+            export function get() {
+                return { count, total: b() + c.total };
+            }
+            export function shadowed() {
+                const count = 1;
+                return count;
+            }
+            "#,
+        )
+        .unwrap();
+
+        // Imports are looked up when used, so the imported scripts may import this one as well
+        assert!(!transpiled.code.contains("const {"));
+        assert!(transpiled
+            .code
+            .contains(r#"count: bevyModJsScriptingImport("scripts/b.ts")["count"]"#));
+        assert!(transpiled
+            .code
+            .contains(r#"bevyModJsScriptingImport("scripts/b.ts")["b"]()"#));
+        assert!(transpiled
+            .code
+            .contains(r#"bevyModJsScriptingImport("scripts/c.ts").total"#));
+        assert!(transpiled.code.contains("return count;"));
+    }
+}
@@ -0,0 +1,124 @@
+//! Helpers for running scripts in a headless app.
+
+#![allow(dead_code)]
+
+use std::{path::PathBuf, time::Duration};
+
+use bevy::{asset::AssetPlugin, ecs::event::ManualEventReader, prelude::*};
+use bevy_mod_js_scripting::{
+    serde_json, JsRuntime, JsRuntimeApi, JsScript, JsScriptError, JsScriptingPlugin,
+};
+
+/// The number of frames to run before giving up on a condition
+const MAX_FRAMES: usize = 1000;
+
+/// A headless app loading scripts from its own temporary asset folder
+pub struct TestApp {
+    pub app: App,
+    asset_folder: PathBuf,
+    error_reader: ManualEventReader<JsScriptError>,
+    /// The script errors sent so far
+    pub errors: Vec<JsScriptError>,
+}
+
+impl TestApp {
+    /// Creates an app with the given scripts, as `(asset path, source)` pairs, in its asset folder.
+    pub fn new(name: &str, scripts: &[(&str, &str)]) -> Self {
+        Self::with_plugin(name, scripts, default())
+    }
+
+    /// Creates an app with the given scripts and scripting plugin settings.
+    pub fn with_plugin(name: &str, scripts: &[(&str, &str)], plugin: JsScriptingPlugin) -> Self {
+        let asset_folder = std::env::temp_dir().join(format!(
+            "bevy_mod_js_scripting_test_{name}_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&asset_folder);
+        for (path, source) in scripts {
+            let path = asset_folder.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin {
+                asset_folder: asset_folder.to_string_lossy().into_owned(),
+                watch_for_changes: false,
+            })
+            .add_plugin(plugin);
+
+        Self {
+            app,
+            asset_folder,
+            error_reader: default(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Runs a single frame, collecting the script errors sent during it.
+    pub fn update(&mut self) {
+        self.app.update();
+
+        let events = self.app.world.resource::<Events<JsScriptError>>();
+        self.errors.extend(self.error_reader.iter(events).cloned());
+    }
+
+    /// Runs frames until `condition` returns `true`, panicking if it takes too long.
+    pub fn update_until(&mut self, mut condition: impl FnMut(&mut Self) -> bool) {
+        for _ in 0..MAX_FRAMES {
+            self.update();
+            if condition(self) {
+                return;
+            }
+            // Give the asset server some time to load the scripts
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("Condition not met after {MAX_FRAMES} frames");
+    }
+
+    /// Returns the handle of the script at the given asset path.
+    pub fn handle(&self, path: &str) -> Handle<JsScript> {
+        self.app.world.resource::<AssetServer>().get_handle(path)
+    }
+
+    /// Returns whether the runtime has loaded the script at the given asset path.
+    pub fn has_loaded(&self, path: &str) -> bool {
+        self.app
+            .world
+            .non_send_resource::<JsRuntime>()
+            .has_loaded(&self.handle(path))
+    }
+
+    /// Runs frames until the script at the given asset path has been loaded by the runtime.
+    pub fn load(&mut self, path: &str) {
+        self.update_until(|app| app.has_loaded(path));
+    }
+
+    /// Calls a function exported by the script at the given asset path.
+    pub fn call(
+        &mut self,
+        path: &str,
+        fn_name: &str,
+        args: &[serde_json::Value],
+    ) -> anyhow::Result<serde_json::Value> {
+        let handle = self.handle(path);
+        let world = &mut self.app.world;
+        let runtime = world.remove_non_send_resource::<JsRuntime>().unwrap();
+        let result = runtime.call(&handle, fn_name, args, world);
+        world.insert_non_send_resource(runtime);
+        result
+    }
+
+    /// Returns the errors sent for the script at the given asset path.
+    pub fn errors_of(&self, path: &str) -> Vec<&JsScriptError> {
+        let handle = self.handle(path);
+        self.errors.iter().filter(|x| x.handle == handle).collect()
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.asset_folder);
+    }
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_mod_js_scripting::{
+    serde_json::json, AddJsSystem, JsScriptComponent, JsScriptingPlugin, ScriptErrorPolicy,
+    ScriptErrorTracker,
+};
+use common::TestApp;
+
+const FAILING_SCRIPT: &str = r#"
+let calls = 0;
+export function update() {
+    calls += 1;
+    throw new Error("Script failure");
+}
+export function getCalls() {
+    return calls;
+}
+"#;
+
+fn failing_app(name: &str, error_policy: ScriptErrorPolicy) -> TestApp {
+    let mut app = TestApp::with_plugin(
+        name,
+        &[("failing.ts", FAILING_SCRIPT)],
+        JsScriptingPlugin {
+            error_policy,
+            ..Default::default()
+        },
+    );
+    app.app.add_js_system("failing.ts");
+    app
+}
+
+#[test]
+#[should_panic(expected = "Script failure")]
+fn panic_policy_panics_on_first_error() {
+    let mut app = failing_app("panic_policy", ScriptErrorPolicy::Panic);
+
+    app.update_until(|_| false);
+}
+
+#[test]
+fn disable_after_policy_stops_running_the_script() {
+    let mut app = failing_app("disable_after_policy", ScriptErrorPolicy::DisableAfter(3));
+
+    app.update_until(|app| app.errors_of("failing.ts").len() >= 3);
+    for _ in 0..5 {
+        app.update();
+    }
+
+    let handle = app.handle("failing.ts");
+    assert_eq!(app.errors_of("failing.ts").len(), 3);
+    assert_eq!(app.call("failing.ts", "getCalls", &[]).unwrap(), json!(3));
+    assert!(app
+        .app
+        .world
+        .resource::<ScriptErrorTracker>()
+        .is_disabled(&handle, None));
+
+    // Re-enabled scripts run again
+    app.app
+        .world
+        .resource_mut::<ScriptErrorTracker>()
+        .enable(&handle, None);
+    app.update();
+
+    assert_eq!(app.call("failing.ts", "getCalls", &[]).unwrap(), json!(4));
+    assert_eq!(app.errors_of("failing.ts").len(), 4);
+}
+
+/// A script whose first instance throws in `update`
+const FAILING_INSTANCE_SCRIPT: &str = r#"
+export function init() {
+    globalThis.failing ??= this.entity.id;
+}
+export function update() {
+    globalThis.updates ??= {};
+    globalThis.updates[this.entity.id] = (globalThis.updates[this.entity.id] ?? 0) + 1;
+    if (this.entity.id === globalThis.failing) {
+        throw new Error("Instance failure");
+    }
+}
+export function getState() {
+    return { failing: globalThis.failing, updates: globalThis.updates };
+}
+"#;
+
+#[test]
+fn disable_after_policy_disables_instances_separately() {
+    let mut app = TestApp::with_plugin(
+        "disable_after_instances",
+        &[("instance.ts", FAILING_INSTANCE_SCRIPT)],
+        JsScriptingPlugin {
+            error_policy: ScriptErrorPolicy::DisableAfter(2),
+            ..Default::default()
+        },
+    );
+    let script: Handle<_> = app.app.world.resource::<AssetServer>().load("instance.ts");
+    let first = app.app.world.spawn(JsScriptComponent(script.clone())).id();
+    let second = app.app.world.spawn(JsScriptComponent(script)).id();
+
+    app.update_until(|app| app.errors_of("instance.ts").len() >= 2);
+    for _ in 0..3 {
+        app.update();
+    }
+    assert_eq!(app.errors_of("instance.ts").len(), 2);
+
+    let state = app.call("instance.ts", "getState", &[]).unwrap();
+    let (failing, working) = if state["failing"] == json!(first.index()) {
+        (first, second)
+    } else {
+        (second, first)
+    };
+    let handle = app.handle("instance.ts");
+    let tracker = app.app.world.resource::<ScriptErrorTracker>();
+    assert!(tracker.is_disabled(&handle, Some(failing)));
+    assert!(!tracker.is_disabled(&handle, Some(working)));
+    assert!(!tracker.is_disabled(&handle, None));
+
+    // The other instance keeps running
+    let updates = |app: &mut TestApp| {
+        let state = app.call("instance.ts", "getState", &[]).unwrap();
+        state["updates"][working.index().to_string()].clone()
+    };
+    let before = updates(&mut app).as_u64().unwrap();
+    app.update();
+    assert_eq!(updates(&mut app), json!(before + 1));
+}
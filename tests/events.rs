@@ -0,0 +1,115 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_mod_js_scripting::{AddJsSystem, JsScriptComponent};
+use common::TestApp;
+
+#[derive(Reflect, FromReflect, Clone, Debug, Default)]
+struct Ping {
+    value: u32,
+}
+
+/// Defines the `Ping` event type in a script
+fn ping_type() -> String {
+    format!(
+        r#"const Ping = {{ typeName: "{}" }};"#,
+        std::any::type_name::<Ping>()
+    )
+}
+
+fn events_app(name: &str, scripts: &[(&str, &str)]) -> TestApp {
+    let mut app = TestApp::new(name, scripts);
+    app.app.register_type::<Ping>().add_js_event::<Ping>();
+    app
+}
+
+#[test]
+fn handlers_run_after_another_handler_throws() {
+    let script = format!(
+        r#"
+        {}
+        let handled = 0;
+        events.on(Ping, () => {{ throw new Error("Handler failure"); }});
+        events.on(Ping, () => {{ handled += 1; }});
+        export function getHandled() {{ return handled; }}
+        "#,
+        ping_type()
+    );
+    let mut app = events_app("event_handler_errors", &[("main.ts", &script)]);
+    app.app.add_js_system("main.ts");
+    app.load("main.ts");
+
+    app.app.world.send_event(Ping { value: 2 });
+    app.update();
+
+    assert_eq!(app.call("main.ts", "getHandled", &[]).unwrap(), 1);
+    let errors = app.errors_of("main.ts");
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0].message.contains("Handler failure"),
+        "{}",
+        errors[0]
+    );
+}
+
+#[test]
+fn instances_handle_events_until_they_are_removed() {
+    let script = format!(
+        r#"
+        {}
+        export function init() {{
+            events.on(Ping, () => {{
+                globalThis.pings = (globalThis.pings ?? 0) + 1;
+            }});
+        }}
+        export function getPings() {{ return globalThis.pings ?? 0; }}
+        "#,
+        ping_type()
+    );
+    let mut app = events_app("instance_events", &[("instance.ts", &script)]);
+    let handle = app.app.world.resource::<AssetServer>().load("instance.ts");
+    let entity = app.app.world.spawn(JsScriptComponent(handle.clone())).id();
+    app.app.world.spawn(JsScriptComponent(handle));
+    app.load("instance.ts");
+    app.update();
+
+    app.app.world.send_event(Ping { value: 1 });
+    app.update();
+    assert_eq!(app.call("instance.ts", "getPings", &[]).unwrap(), 2);
+
+    app.app.world.despawn(entity);
+    app.update();
+    app.app.world.send_event(Ping { value: 1 });
+    app.update();
+    assert_eq!(app.call("instance.ts", "getPings", &[]).unwrap(), 3);
+    assert!(app.errors.is_empty(), "{:?}", app.errors);
+}
+
+#[test]
+fn instances_read_events_separately() {
+    let script = format!(
+        r#"
+        {}
+        export function update() {{
+            const pings = world.events(Ping).read().length;
+            globalThis.pings = (globalThis.pings ?? 0) + pings;
+        }}
+        export function getPings() {{ return globalThis.pings ?? 0; }}
+        "#,
+        ping_type()
+    );
+    let mut app = events_app("instance_event_readers", &[("instance.ts", &script)]);
+    let handle = app.app.world.resource::<AssetServer>().load("instance.ts");
+    app.app.world.spawn(JsScriptComponent(handle.clone()));
+    app.app.world.spawn(JsScriptComponent(handle));
+    app.load("instance.ts");
+    app.update();
+
+    // Every instance reads the event
+    app.app.world.send_event(Ping { value: 1 });
+    app.update();
+    assert_eq!(app.call("instance.ts", "getPings", &[]).unwrap(), 2);
+    app.update();
+    assert_eq!(app.call("instance.ts", "getPings", &[]).unwrap(), 2);
+    assert!(app.errors.is_empty(), "{:?}", app.errors);
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_mod_js_scripting::{serde_json::json, AddJsSystem, JsScript};
+use common::TestApp;
+
+#[test]
+fn scripts_importing_each_other_are_loaded() {
+    let mut app = TestApp::new(
+        "import_cycle",
+        &[
+            (
+                "a.ts",
+                r#"
+                import { b } from "./b";
+                export function a() { return "a"; }
+                export function ab() { return a() + b(); }
+                "#,
+            ),
+            (
+                "b.ts",
+                r#"
+                import { a } from "./a";
+                export function b() { return "b"; }
+                export function ba() { return b() + a(); }
+                "#,
+            ),
+        ],
+    );
+    app.app.add_js_system("a.ts");
+
+    app.update_until(|app| app.has_loaded("a.ts") && app.has_loaded("b.ts"));
+
+    assert!(app.errors.is_empty(), "{:?}", app.errors);
+    assert_eq!(app.call("a.ts", "ab", &[]).unwrap(), json!("ab"));
+    assert_eq!(app.call("b.ts", "ba", &[]).unwrap(), json!("ba"));
+}
+
+#[test]
+fn scripts_importing_a_script_that_fails_to_transpile_fail_to_load() {
+    let mut app = TestApp::new(
+        "import_transpile_error",
+        &[
+            (
+                "main.ts",
+                r#"import { util } from "./util"; export const x = 1;"#,
+            ),
+            ("util.ts", "export function util( {"),
+        ],
+    );
+    app.app.add_js_system("main.ts");
+
+    app.update_until(|app| !app.errors_of("main.ts").is_empty());
+
+    let errors = app.errors_of("main.ts");
+    assert!(errors[0].message.contains("util.ts"), "{}", errors[0]);
+    assert!(!app.has_loaded("main.ts"));
+}
+
+#[test]
+fn scripts_importing_a_script_that_throws_fail_to_load() {
+    let mut app = TestApp::new(
+        "import_evaluation_error",
+        &[
+            (
+                "main.ts",
+                r#"import { util } from "./util"; export const x = 1;"#,
+            ),
+            (
+                "lib.ts",
+                r#"import { util } from "./util"; export const y = 1;"#,
+            ),
+            (
+                "util.ts",
+                r#"throw new Error("Broken"); export function util() {}"#,
+            ),
+        ],
+    );
+    app.app.add_js_system("main.ts").add_js_system("lib.ts");
+
+    app.update_until(|app| {
+        !app.errors_of("main.ts").is_empty() && !app.errors_of("lib.ts").is_empty()
+    });
+
+    assert!(app.errors_of("util.ts")[0].message.contains("Broken"));
+    for path in ["main.ts", "lib.ts"] {
+        let errors = app.errors_of(path);
+        assert!(errors[0].message.contains("util.ts"), "{}", errors[0]);
+        assert!(!app.has_loaded(path));
+    }
+}
+
+#[test]
+fn scripts_importing_a_removed_script_are_unloaded() {
+    let mut app = TestApp::new(
+        "import_removed",
+        &[
+            (
+                "main.ts",
+                r#"import { util } from "./util"; export function run() { return util(); }"#,
+            ),
+            ("util.ts", "export function util() { return 1; }"),
+        ],
+    );
+    app.app.add_js_system("main.ts");
+    app.load("main.ts");
+
+    let util = app.handle("util.ts");
+    app.app
+        .world
+        .resource_mut::<Assets<JsScript>>()
+        .remove(&util);
+    app.update();
+
+    assert!(!app.has_loaded("util.ts"));
+    assert!(!app.has_loaded("main.ts"));
+}
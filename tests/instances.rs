@@ -0,0 +1,105 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_mod_js_scripting::{JsScript, JsScriptComponent};
+use common::TestApp;
+
+/// A script counting the lifecycle calls of its instances in globals, so that they can still be
+/// read once the script has been unloaded
+const INSTANCE_SCRIPT: &str = r#"
+export function init() {
+    globalThis.inits = (globalThis.inits ?? 0) + 1;
+}
+export function update() {
+    this.updates = (this.updates ?? 0) + 1;
+    globalThis.updates = (globalThis.updates ?? 0) + 1;
+}
+export function onUnload() {
+    globalThis.unloads = (globalThis.unloads ?? 0) + 1;
+}
+"#;
+
+const PROBE_SCRIPT: &str = r#"
+export function counts() {
+    return {
+        inits: globalThis.inits ?? 0,
+        updates: globalThis.updates ?? 0,
+        unloads: globalThis.unloads ?? 0,
+    };
+}
+"#;
+
+fn instances_app(name: &str) -> TestApp {
+    let mut app = TestApp::new(
+        name,
+        &[("instance.ts", INSTANCE_SCRIPT), ("probe.ts", PROBE_SCRIPT)],
+    );
+    // The probe script is kept loaded by attaching it to an entity
+    let probe: Handle<JsScript> = app.app.world.resource::<AssetServer>().load("probe.ts");
+    app.app.world.spawn(JsScriptComponent(probe));
+    app
+}
+
+fn counts(app: &mut TestApp) -> (u64, u64, u64) {
+    let counts = app.call("probe.ts", "counts", &[]).unwrap();
+    let count = |key: &str| counts[key].as_u64().unwrap();
+    (count("inits"), count("updates"), count("unloads"))
+}
+
+fn spawn_instance(app: &mut TestApp) -> Entity {
+    let script = app.app.world.resource::<AssetServer>().load("instance.ts");
+    app.app.world.spawn(JsScriptComponent(script)).id()
+}
+
+#[test]
+fn spawned_entities_get_an_instance() {
+    let mut app = instances_app("instance_spawn");
+    app.load("probe.ts");
+
+    spawn_instance(&mut app);
+    spawn_instance(&mut app);
+    app.update_until(|app| counts(app).0 == 2);
+
+    let (_, updates, _) = counts(&mut app);
+    app.update();
+    assert_eq!(counts(&mut app).1, updates + 2);
+    assert!(app.errors.is_empty(), "{:?}", app.errors);
+}
+
+#[test]
+fn despawned_entities_unload_their_instance() {
+    let mut app = instances_app("instance_despawn");
+    app.load("probe.ts");
+
+    let entity = spawn_instance(&mut app);
+    spawn_instance(&mut app);
+    app.update_until(|app| counts(app).0 == 2);
+
+    app.app.world.despawn(entity);
+    app.update();
+    assert_eq!(counts(&mut app).2, 1);
+
+    let (_, updates, _) = counts(&mut app);
+    app.update();
+    assert_eq!(counts(&mut app).1, updates + 1);
+}
+
+#[test]
+fn unloading_a_script_unloads_its_instances() {
+    let mut app = instances_app("instance_unload");
+    app.load("probe.ts");
+
+    spawn_instance(&mut app);
+    spawn_instance(&mut app);
+    app.update_until(|app| counts(app).0 == 2);
+
+    let script = app.handle("instance.ts");
+    app.app
+        .world
+        .resource_mut::<Assets<JsScript>>()
+        .remove(&script);
+    app.update();
+
+    assert!(!app.has_loaded("instance.ts"));
+    assert_eq!(counts(&mut app).2, 2);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_mod_js_scripting::{serde_json::json, AddJsSystem};
+use common::TestApp;
+
+#[test]
+fn startup_runs_once_at_the_start_of_the_next_frame() {
+    let mut app = TestApp::new(
+        "startup",
+        &[(
+            "main.ts",
+            r#"
+            const runs: string[] = [];
+            export function startup() { runs.push("startup"); }
+            export function first() { runs.push("first"); }
+            export function getRuns() { return runs; }
+            "#,
+        )],
+    );
+    app.app.add_js_system("main.ts");
+    app.load("main.ts");
+    app.update();
+    app.update();
+
+    assert!(app.errors.is_empty(), "{:?}", app.errors);
+    assert_eq!(
+        app.call("main.ts", "getRuns", &[]).unwrap(),
+        json!(["startup", "first", "first"])
+    );
+}
+
+#[derive(Reflect, Debug, Clone, PartialEq, Eq, Hash)]
+enum GameState {
+    Menu,
+    Level(u32),
+}
+
+#[test]
+fn state_functions_are_keyed_by_variant_name() {
+    let mut app = TestApp::new(
+        "state_fns",
+        &[(
+            "main.ts",
+            r#"
+            const runs: string[] = [];
+            export const onEnter = {
+                Level() { runs.push("enter Level"); },
+            };
+            export const onExit = {
+                Menu() { runs.push("exit Menu"); },
+            };
+            export function getRuns() { return runs; }
+            "#,
+        )],
+    );
+    app.app
+        .add_state(GameState::Menu)
+        .add_js_state(GameState::Menu)
+        .add_js_state(GameState::Level(1))
+        .add_js_system("main.ts");
+    app.load("main.ts");
+
+    app.app
+        .world
+        .resource_mut::<State<GameState>>()
+        .set(GameState::Level(1))
+        .unwrap();
+    app.update();
+
+    assert!(app.errors.is_empty(), "{:?}", app.errors);
+    assert_eq!(
+        app.call("main.ts", "getRuns", &[]).unwrap(),
+        json!(["exit Menu", "enter Level"])
+    );
+}
@@ -0,0 +1,210 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_mod_js_scripting::{serde_json::json, AddJsSystem};
+use common::TestApp;
+
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct Health {
+    value: f32,
+}
+
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct Armor {
+    value: f32,
+}
+
+/// A component that is registered, but never added to an entity, so it has no component id
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct Shield;
+
+/// Creates an app with a script that can use the `Health`, `Armor` and `Shield` types
+fn query_app(name: &str, script: &str) -> TestApp {
+    let type_name =
+        |name: &str, type_name: &str| format!("const {name} = {{ typeName: \"{type_name}\" }};\n");
+    let script = [
+        type_name("Health", std::any::type_name::<Health>()),
+        type_name("Armor", std::any::type_name::<Armor>()),
+        type_name("Shield", std::any::type_name::<Shield>()),
+        script.to_owned(),
+    ]
+    .concat();
+    let mut app = TestApp::new(name, &[("main.ts", &script)]);
+    app.app
+        .register_type::<Health>()
+        .register_type::<Armor>()
+        .register_type::<Shield>()
+        .add_js_system("main.ts");
+    app.load("main.ts");
+    app
+}
+
+const FILTER_SCRIPT: &str = r#"
+const ids = (query) => query.map(({ entity }) => entity.id).sort((a, b) => a - b);
+
+export function changed() {
+    return ids(world.query(Health).changed(Health));
+}
+export function withShield() {
+    return ids(world.query(Health).with(Shield));
+}
+export function withoutShield() {
+    return ids(world.query(Health).without(Shield));
+}
+export function shieldChanged() {
+    return ids(world.query(Health).changed(Shield));
+}
+"#;
+
+#[test]
+fn change_filters_are_relative_to_the_previous_run_of_the_script() {
+    let mut app = query_app("changed", FILTER_SCRIPT);
+    let first = app.app.world.spawn(Health { value: 1.0 }).id();
+    let second = app.app.world.spawn(Health { value: 2.0 }).id();
+
+    // Everything is new on the first run
+    assert_eq!(
+        app.call("main.ts", "changed", &[]).unwrap(),
+        json!([first.index(), second.index()])
+    );
+    assert_eq!(app.call("main.ts", "changed", &[]).unwrap(), json!([]));
+
+    app.app.world.get_mut::<Health>(second).unwrap().value = 3.0;
+    assert_eq!(
+        app.call("main.ts", "changed", &[]).unwrap(),
+        json!([second.index()])
+    );
+    assert_eq!(app.call("main.ts", "changed", &[]).unwrap(), json!([]));
+}
+
+#[test]
+fn filters_on_components_without_an_id_match_without_errors() {
+    let mut app = query_app("filters_without_id", FILTER_SCRIPT);
+    let entity = app.app.world.spawn(Health { value: 1.0 }).id();
+
+    assert_eq!(app.call("main.ts", "withShield", &[]).unwrap(), json!([]));
+    assert_eq!(
+        app.call("main.ts", "withoutShield", &[]).unwrap(),
+        json!([entity.index()])
+    );
+    assert_eq!(
+        app.call("main.ts", "shieldChanged", &[]).unwrap(),
+        json!([])
+    );
+}
+
+const OPTIONAL_SCRIPT: &str = r#"
+const query = () => world.query(Health, Optional(Armor), Optional(Shield));
+const nulls = (components) => components.map((component) => component === null);
+const byId = (items) => items
+    .sort((a, b) => a.entity.id - b.entity.id)
+    .map(({ entity, components }) => [entity.id, nulls(components)]);
+
+export function collected() {
+    return byId(query().map((item) => item));
+}
+export function iterated() {
+    const items = [];
+    for (const item of query()) {
+        items.push(item);
+    }
+    return byId(items);
+}
+export function get(entity) {
+    return nulls(query().get(entity));
+}
+"#;
+
+#[test]
+fn optional_components_are_null_for_entities_without_them() {
+    let mut app = query_app("optional", OPTIONAL_SCRIPT);
+    let armored = app
+        .app
+        .world
+        .spawn((Health { value: 1.0 }, Armor { value: 2.0 }))
+        .id();
+    let unarmored = app.app.world.spawn(Health { value: 1.0 }).id();
+
+    let expected = json!([
+        [armored.index(), [false, false, true]],
+        [unarmored.index(), [false, true, true]],
+    ]);
+    assert_eq!(app.call("main.ts", "collected", &[]).unwrap(), expected);
+    assert_eq!(app.call("main.ts", "iterated", &[]).unwrap(), expected);
+
+    let js_entity =
+        |entity: Entity| json!({ "id": entity.index(), "generation": entity.generation() });
+    assert_eq!(
+        app.call("main.ts", "get", &[js_entity(armored)]).unwrap(),
+        json!([false, false, true])
+    );
+    assert_eq!(
+        app.call("main.ts", "get", &[js_entity(unarmored)]).unwrap(),
+        json!([false, true, true])
+    );
+}
+
+const KEPT_QUERY_SCRIPT: &str = r#"
+const healthy = world.query(Health);
+
+export function count() {
+    return healthy.collect().length;
+}
+export function indexed() {
+    return healthy[0];
+}
+export function iteratedCount() {
+    let count = 0;
+    for (const _ of healthy) {
+        count += 1;
+    }
+    return count;
+}
+"#;
+
+#[test]
+fn kept_queries_are_evaluated_again_on_every_access() {
+    let mut app = query_app("kept_query", KEPT_QUERY_SCRIPT);
+    app.app.world.spawn(Health { value: 1.0 });
+
+    assert_eq!(app.call("main.ts", "count", &[]).unwrap(), json!(1));
+
+    // The new entity is in another archetype
+    app.app
+        .world
+        .spawn((Health { value: 1.0 }, Armor { value: 1.0 }));
+    assert_eq!(app.call("main.ts", "count", &[]).unwrap(), json!(2));
+    assert_eq!(app.call("main.ts", "iteratedCount", &[]).unwrap(), json!(2));
+
+    // The items can't be accessed without collecting the query
+    let error = app.call("main.ts", "indexed", &[]).unwrap_err();
+    assert!(error.to_string().contains("collect()"), "{error}");
+}
+
+const PREPARED_QUERY_SCRIPT: &str = r#"
+// The components only get an id once they have been added to an entity
+let armored;
+
+export function count() {
+    armored ??= world.prepareQuery(Health).with(Armor);
+    return armored.collect().length;
+}
+"#;
+
+#[test]
+fn prepared_queries_see_new_archetypes() {
+    let mut app = query_app("prepared_query", PREPARED_QUERY_SCRIPT);
+    app.app
+        .world
+        .spawn((Health { value: 1.0 }, Armor { value: 1.0 }));
+    assert_eq!(app.call("main.ts", "count", &[]).unwrap(), json!(1));
+
+    // The cached queries are updated with the new archetype
+    app.app
+        .world
+        .spawn((Health { value: 1.0 }, Armor { value: 1.0 }, Shield));
+    assert_eq!(app.call("main.ts", "count", &[]).unwrap(), json!(2));
+}
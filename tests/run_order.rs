@@ -0,0 +1,71 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_mod_js_scripting::{serde_json::json, ActiveScripts, AddJsSystem, JsScriptLabel};
+use common::TestApp;
+
+/// A script recording the order that the scripts run in, in a global shared by the scripts
+fn recording_script(name: &str) -> String {
+    format!(
+        r#"
+        export function update() {{
+            globalThis.runs ??= [];
+            globalThis.runs.push("{name}");
+        }}
+        export function takeRuns() {{
+            const runs = globalThis.runs ?? [];
+            globalThis.runs = [];
+            return runs;
+        }}
+        "#
+    )
+}
+
+#[test]
+fn run_order_follows_changes_to_the_active_scripts() {
+    let (a, b) = (recording_script("a"), recording_script("b"));
+    let mut app = TestApp::new("run_order", &[("a.ts", &a), ("b.ts", &b)]);
+    app.app.add_js_system("a.ts").add_js_system("b.ts");
+    app.update_until(|app| app.has_loaded("a.ts") && app.has_loaded("b.ts"));
+
+    app.call("a.ts", "takeRuns", &[]).unwrap();
+    app.update();
+    assert_eq!(
+        app.call("a.ts", "takeRuns", &[]).unwrap(),
+        json!(["a", "b"])
+    );
+
+    let b_handle = app.handle("b.ts");
+    app.app
+        .world
+        .resource_mut::<ActiveScripts>()
+        .get_mut(&b_handle)
+        .unwrap()
+        .priority = 1;
+    app.update();
+    assert_eq!(
+        app.call("a.ts", "takeRuns", &[]).unwrap(),
+        json!(["b", "a"])
+    );
+}
+
+#[test]
+fn script_functions_can_be_ordered_by_their_labels() {
+    let (a, b) = (recording_script("a"), recording_script("b"));
+    let mut app = TestApp::new("script_labels", &[("a.ts", &a), ("b.ts", &b)]);
+    app.app
+        .add_js_system("a.ts")
+        .add_js_system("b.ts")
+        .add_js_script_fn_with("a.ts", "update", CoreStage::Update, |system| {
+            system.after(JsScriptLabel::new("b.ts", "update"))
+        })
+        .add_js_script_fn_with("b.ts", "update", CoreStage::Update, |system| system);
+    app.update_until(|app| app.has_loaded("a.ts") && app.has_loaded("b.ts"));
+
+    app.call("a.ts", "takeRuns", &[]).unwrap();
+    app.update();
+    assert_eq!(
+        app.call("a.ts", "takeRuns", &[]).unwrap(),
+        json!(["b", "a"])
+    );
+}
@@ -0,0 +1,222 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_mod_js_scripting::{
+    serde_json::{self, json},
+    AddJsSystem,
+};
+use common::TestApp;
+
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct Health {
+    value: f32,
+}
+
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+struct Score {
+    points: f32,
+}
+
+#[derive(Component, Reflect)]
+#[reflect(Component, Default)]
+struct Target {
+    entity: Entity,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self {
+            entity: Entity::from_raw(u32::MAX),
+        }
+    }
+}
+
+/// Creates an app running a script that can use the `Health`, `Score` and `Target` types
+fn world_app(name: &str, script: &str) -> TestApp {
+    let script = format!(
+        "const Health = {{ typeName: \"{}\" }};\nconst Score = {{ typeName: \"{}\" }};\n\
+        const Target = {{ typeName: \"{}\" }};\n{script}",
+        std::any::type_name::<Health>(),
+        std::any::type_name::<Score>(),
+        std::any::type_name::<Target>(),
+    );
+    let mut app = TestApp::new(name, &[("main.ts", &script)]);
+    app.app
+        .register_type::<Health>()
+        .register_type::<Score>()
+        .register_type::<Target>()
+        .add_js_system("main.ts");
+    app.load("main.ts");
+    app
+}
+
+fn js_entity(entity: Entity) -> serde_json::Value {
+    json!({ "id": entity.index(), "generation": entity.generation() })
+}
+
+const ENTITY_SCRIPT: &str = r#"
+export function despawn(entity, recursive) {
+    world.despawn(entity, { recursive });
+}
+export function remove(entity) {
+    world.remove(entity, Health);
+}
+export function has(entity) {
+    return world.has(entity, Health);
+}
+"#;
+
+#[test]
+fn scripts_despawn_entities() {
+    let mut app = world_app("despawn", ENTITY_SCRIPT);
+    let parent = app.app.world.spawn_empty().id();
+    let child = app.app.world.spawn_empty().id();
+    app.app.world.entity_mut(parent).push_children(&[child]);
+
+    app.call("main.ts", "despawn", &[js_entity(parent), json!(false)])
+        .unwrap();
+
+    assert!(app.app.world.get_entity(parent).is_none());
+    assert!(app.app.world.get_entity(child).is_some());
+}
+
+#[test]
+fn scripts_despawn_entities_recursively() {
+    let mut app = world_app("despawn_recursive", ENTITY_SCRIPT);
+    let parent = app.app.world.spawn_empty().id();
+    let child = app.app.world.spawn_empty().id();
+    let grandchild = app.app.world.spawn_empty().id();
+    app.app.world.entity_mut(parent).push_children(&[child]);
+    app.app.world.entity_mut(child).push_children(&[grandchild]);
+
+    app.call("main.ts", "despawn", &[js_entity(parent), json!(true)])
+        .unwrap();
+
+    for entity in [parent, child, grandchild] {
+        assert!(app.app.world.get_entity(entity).is_none());
+    }
+}
+
+#[test]
+fn scripts_remove_and_check_components() {
+    let mut app = world_app("remove_component", ENTITY_SCRIPT);
+    let entity = app.app.world.spawn(Health { value: 1.0 }).id();
+
+    assert_eq!(
+        app.call("main.ts", "has", &[js_entity(entity)]).unwrap(),
+        true
+    );
+    app.call("main.ts", "remove", &[js_entity(entity)]).unwrap();
+
+    assert!(app.app.world.get::<Health>(entity).is_none());
+    assert_eq!(
+        app.call("main.ts", "has", &[js_entity(entity)]).unwrap(),
+        false
+    );
+}
+
+const RESOURCE_SCRIPT: &str = r#"
+export function insertScore(points) {
+    world.insertResource(Score, Value.create(Score, { points }));
+}
+export function removeScore() {
+    world.removeResource(Score);
+}
+export function hasScore() {
+    return world.hasResource(Score);
+}
+"#;
+
+#[test]
+fn scripts_insert_and_remove_resources() {
+    let mut app = world_app("resources", RESOURCE_SCRIPT);
+
+    assert_eq!(app.call("main.ts", "hasScore", &[]).unwrap(), false);
+    app.call("main.ts", "insertScore", &[json!(2.5)]).unwrap();
+    assert_eq!(app.app.world.resource::<Score>().points, 2.5);
+    assert_eq!(app.call("main.ts", "hasScore", &[]).unwrap(), true);
+
+    app.call("main.ts", "removeScore", &[]).unwrap();
+    assert!(!app.app.world.contains_resource::<Score>());
+    assert_eq!(app.call("main.ts", "hasScore", &[]).unwrap(), false);
+}
+
+const RETAIN_SCRIPT: &str = r#"
+let score;
+
+export function retainScore() {
+    score = Value.retain(world.resource(Score));
+}
+export function releaseScore() {
+    Value.release(score);
+}
+export function readScore() {
+    return score.points;
+}
+"#;
+
+#[test]
+fn retained_value_refs_are_kept_until_released() {
+    let mut app = world_app("retain", RETAIN_SCRIPT);
+    app.app.world.insert_resource(Score { points: 2.5 });
+
+    app.call("main.ts", "retainScore", &[]).unwrap();
+    app.update();
+    assert_eq!(app.call("main.ts", "readScore", &[]).unwrap(), 2.5);
+
+    // Released value refs stay valid until the end of the frame
+    app.call("main.ts", "releaseScore", &[]).unwrap();
+    assert_eq!(app.call("main.ts", "readScore", &[]).unwrap(), 2.5);
+    app.update();
+    assert!(app.call("main.ts", "readScore", &[]).is_err());
+}
+
+#[test]
+fn retaining_entities_does_nothing() {
+    let mut app = world_app(
+        "retain_entity",
+        r#"
+export function retainEntity() {
+    const entity = world.spawn();
+    Value.release(Value.retain(entity));
+    return Value.retain(entity) === entity;
+}
+"#,
+    );
+
+    assert_eq!(app.call("main.ts", "retainEntity", &[]).unwrap(), true);
+}
+
+const TARGET_SCRIPT: &str = r#"
+export function insertTarget(entity) {
+    const target = world.spawn();
+    world.insert(entity, Target, Value.create(Target, { entity: target }));
+    return target;
+}
+export function patchTarget(entity, target) {
+    Value.patch(world.get(entity, Target), { entity: target });
+}
+"#;
+
+#[test]
+fn entity_fields_can_be_created_and_patched() {
+    let mut app = world_app("entity_fields", TARGET_SCRIPT);
+    let entity = app.app.world.spawn_empty().id();
+
+    let target = app
+        .call("main.ts", "insertTarget", &[js_entity(entity)])
+        .unwrap();
+    let target_entity = app.app.world.get::<Target>(entity).unwrap().entity;
+    assert_eq!(js_entity(target_entity), target);
+
+    let other = app.app.world.spawn_empty().id();
+    app.call(
+        "main.ts",
+        "patchTarget",
+        &[js_entity(entity), js_entity(other)],
+    )
+    .unwrap();
+    assert_eq!(app.app.world.get::<Target>(entity).unwrap().entity, other);
+}
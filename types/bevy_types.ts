@@ -140,7 +140,7 @@ const Camera3d: BevyType<Camera3d> = { typeName: "bevy_core_pipeline::core_3d::c
 const Camera3dDepthLoadOp: BevyType<Camera3dDepthLoadOp> = { typeName: "bevy_core_pipeline::core_3d::camera_3d::Camera3dDepthLoadOp" };
 const Tonemapping: BevyType<Tonemapping> = { typeName: "bevy_core_pipeline::tonemapping::Tonemapping" };
 // bevy_ecs
-type Entity = unknown;
+type Entity = { readonly id: number, readonly generation: number };
 
 const Entity: BevyType<Entity> = { typeName: "bevy_ecs::entity::Entity" };
 // bevy_gltf
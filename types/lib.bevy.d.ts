@@ -25,6 +25,8 @@ type RecursivePartial<T> = {
 declare interface ValueGlobal {
   create<T>(t: BevyType<T>, patch?: RecursivePartial<T>): T;
   patch<T>(value: T, patch: RecursivePartial<T>): T;
+  retain<T>(value: T): T;
+  release(value: unknown): void;
 }
 
 declare let Value: ValueGlobal;
@@ -52,23 +54,58 @@ type BevyType<T> = {
   typeName: string;
 };
 
-type ExtractBevyType<T> = T extends BevyType<infer U>
+type OptionalParameter<T> = { optional: T };
+
+type ExtractBevyType<T> = T extends OptionalParameter<infer P>
+  ? ExtractBevyType<P> | null
+  : T extends BevyType<infer U>
   ? U
   : T extends ComponentId
   ? Value
   : never;
 type MapQueryArgs<Q> = { [C in keyof Q]: ExtractBevyType<Q[C]> };
 
-type QueryParameter = BevyType<unknown> | ComponentId;
+type QueryParameter =
+  | BevyType<unknown>
+  | ComponentId
+  | OptionalParameter<BevyType<unknown> | ComponentId>;
+
+declare function Optional<T extends BevyType<unknown> | ComponentId>(
+  component: T
+): OptionalParameter<T>;
 type QueryItem<Q> = {
   entity: Entity;
   components: MapQueryArgs<Q>;
 };
 
+declare type QueryFilterTerm = unknown;
+
+declare interface QueryFilterGlobal {
+  with(component: QueryParameter): QueryFilterTerm;
+  without(component: QueryParameter): QueryFilterTerm;
+  changed(component: QueryParameter): QueryFilterTerm;
+  added(component: QueryParameter): QueryFilterTerm;
+  or(...filters: QueryFilterTerm[]): QueryFilterTerm;
+}
+
+declare let QueryFilter: QueryFilterGlobal;
+
 declare class QueryItems<Q> extends Array<QueryItem<Q>> {
   get(entity: Entity): MapQueryArgs<Q> | undefined;
 }
 
+// The array methods of a query collect its items on every call, and the items can only be
+// accessed by index once collected.
+declare interface Query<Q> extends Omit<QueryItems<Q>, number | "length"> {
+  collect(): QueryItems<Q>;
+
+  with(component: QueryParameter): Query<Q>;
+  without(component: QueryParameter): Query<Q>;
+  changed(component: QueryParameter): Query<Q>;
+  added(component: QueryParameter): Query<Q>;
+  or(...filters: QueryFilterTerm[]): Query<Q>;
+}
+
 declare class World {
   get components(): ComponentInfo[];
   get resources(): ComponentInfo[];
@@ -76,11 +113,35 @@ declare class World {
 
   resource(componentId: ComponentId): Value | null;
   resource<T>(type: BevyType<T>): T | null;
-
-  query<Q extends QueryParameter[]>(...query: Q): QueryItems<Q>;
+  insertResource(componentId: ComponentId, value: Value): void;
+  insertResource<T>(type: BevyType<T>, value: T): void;
+  removeResource(componentId: ComponentId): void;
+  removeResource<T>(type: BevyType<T>): void;
+  hasResource(componentId: ComponentId): boolean;
+  hasResource<T>(type: BevyType<T>): boolean;
+
+  query<Q extends QueryParameter[]>(...query: Q): Query<Q>;
+  prepareQuery<Q extends QueryParameter[]>(...query: Q): Query<Q>;
   get<T>(entity: Entity, component: BevyType<T>): T | undefined;
   insert<T>(entity: Entity, component: BevyType<T>, value: T): void;
   spawn(): Entity;
+  despawn(entity: Entity, options?: { recursive?: boolean }): void;
+  remove<T>(entity: Entity, component: BevyType<T>): void;
+  has<T>(entity: Entity, component: BevyType<T>): boolean;
+
+  events<T>(eventType: BevyType<T>): EventReader<T>;
+  sendEvent<T>(eventType: BevyType<T>, value: T): void;
+}
+
+declare interface EventReader<T> {
+  read(): T[];
 }
 
 declare let world: World;
+
+// events.js
+declare interface EventsGlobal {
+  on<T>(eventType: BevyType<T>, handler: (event: T) => void): void;
+}
+
+declare let events: EventsGlobal;